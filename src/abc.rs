@@ -0,0 +1,242 @@
+//! Renders scores as ABC notation, with one `V:` voice per voice of the score.
+
+use std::fmt::Write;
+
+use crate::{
+    score::note_values, Articulation, Clef, Event, Hairpin, Key, Pitch, Rehearsal, Score, Spelling,
+    WriteMusic, CYCLE, STEP,
+};
+
+/// The letters of the natural notes, as ABC names them in the octave of middle C.
+const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+/// Renders a complete ABC tune for the score.
+pub fn render(score: &Score) -> String {
+    let config = &score.config;
+    let tempo = config.tempo;
+    let mut out = format!(
+        "X:1
+T:Passacaglia
+% generated by passacaglia
+% {config:?}
+M:{}
+L:1/{}
+Q:1/4={tempo}
+",
+        score.time,
+        STEP * 4
+    );
+    let mut number = 1;
+    for part in &score.parts {
+        let clef = match part.clef {
+            Clef::Treble => "treble",
+            Clef::Bass => "bass",
+            Clef::Treble8 => "treble-8",
+        };
+        // Transposing instruments' voices are written in their own key, and played as written
+        // less the transposition.
+        let (_, half_steps) = part.instrument.transposition();
+        let transpose = if half_steps != 0 {
+            format!(" transpose={}", -half_steps)
+        } else {
+            String::new()
+        };
+        for _ in &part.voices {
+            writeln!(
+                out,
+                "V:{number} clef={clef}{transpose} name=\"{}\"",
+                part.name
+            )
+            .unwrap();
+            number += 1;
+        }
+    }
+    writeln!(out, "K:{}", key_name(score.key)).unwrap();
+    let mut number = 1;
+    for part in &score.parts {
+        let (key, voices) = part.written(score.key);
+        for voice in &voices {
+            let mut writer = AbcWriter::new(key, score.time.measure());
+            if number == 1 {
+                writer.rehearsals = score.rehearsals.clone();
+            }
+            voice.render(&mut writer);
+            writeln!(out, "V:{number}").unwrap();
+            writeln!(out, "%%MIDI program {}", part.instrument.program()).unwrap();
+            if key != score.key {
+                writeln!(out, "[K:{}]", key_name(key)).unwrap();
+            }
+            out.push_str(writer.output.trim_end());
+            out.push_str("]\n");
+            number += 1;
+        }
+    }
+    out
+}
+
+/// The key as an ABC `K:` field names it, such as "Bb" or "F#m".
+fn key_name(key: Key) -> String {
+    let tonic = key.spell(Pitch(key.tonic()));
+    let accidental = match tonic.alter {
+        1 => "#",
+        -1 => "b",
+        _ => "",
+    };
+    let mode = if key.minor { "m" } else { "" };
+    format!("{}{accidental}{mode}", tonic.step())
+}
+
+/// Writes the events of a single voice as ABC music.
+pub struct AbcWriter {
+    key: Key,
+    /// The length of a measure, in steps.
+    measure: u32,
+    measure_left: u32,
+    measures: u32,
+    /// The accidentals written so far in the current measure, by letter and octave.
+    accidentals: Vec<(usize, i32, i32)>,
+    /// The hairpin which has begun but not yet ended.
+    hairpin: Option<Hairpin>,
+    /// The rehearsal marks to write above the voice, by step.
+    pub rehearsals: Vec<Rehearsal>,
+    pub output: String,
+}
+impl AbcWriter {
+    pub fn new(key: Key, measure: u32) -> Self {
+        Self {
+            key,
+            measure,
+            measure_left: measure,
+            measures: 0,
+            accidentals: vec![],
+            hairpin: None,
+            rehearsals: vec![],
+            output: String::new(),
+        }
+    }
+    fn write_pitch(&mut self, spelling: Spelling) {
+        let Spelling {
+            letter,
+            alter,
+            octave,
+        } = spelling;
+        let step = LETTERS[letter];
+        // Accidentals last until the end of the measure, and otherwise come from the key.
+        let current = self
+            .accidentals
+            .iter()
+            .rev()
+            .find(|&&(l, _, o)| l == letter && o == octave)
+            .map_or(self.key.signature(letter), |&(_, a, _)| a);
+        if alter != current {
+            self.output.push_str(match alter {
+                -2 => "__",
+                -1 => "_",
+                0 => "=",
+                1 => "^",
+                2 => "^^",
+                _ => unreachable!(),
+            });
+            self.accidentals.push((letter, alter, octave));
+        }
+        if octave >= 5 {
+            self.output.push(step.to_ascii_lowercase());
+            for _ in 5..octave {
+                self.output.push('\'');
+            }
+        } else {
+            self.output.push(step);
+            for _ in octave..4 {
+                self.output.push(',');
+            }
+        }
+    }
+    /// Writes the dynamics marks beginning at an event, ending any hairpin they interrupt.
+    fn write_dynamics(&mut self, event: &Event) {
+        if event.dynamic.is_some() || event.hairpin.is_some() {
+            match self.hairpin.take() {
+                Some(Hairpin::Crescendo) => self.output.push_str("!crescendo)!"),
+                Some(Hairpin::Decrescendo) => self.output.push_str("!diminuendo)!"),
+                None => {}
+            }
+        }
+        if let Some(dynamic) = event.dynamic {
+            write!(&mut self.output, "!{}!", dynamic.to_name()).unwrap();
+        }
+        match event.hairpin {
+            Some(Hairpin::Crescendo) => self.output.push_str("!crescendo(!"),
+            Some(Hairpin::Decrescendo) => self.output.push_str("!diminuendo(!"),
+            None => {}
+        }
+        self.hairpin = self.hairpin.or(event.hairpin);
+    }
+    /// Writes a note, chord, or rest (if there are no pitches), split at barlines.
+    fn write_pitches(
+        &mut self,
+        chord: &[Spelling],
+        duration: u32,
+        articulation: Option<Articulation>,
+    ) {
+        if let Some(articulation) = articulation {
+            self.output.push_str(match articulation {
+                Articulation::Accent => "!accent!",
+            });
+        }
+        let mut left = duration;
+        while left > 0 {
+            let length = left.min(self.measure_left);
+            if self.measure_left == self.measure {
+                let start = self.measures * self.measure;
+                for rehearsal in self.rehearsals.iter().filter(|r| r.start == start) {
+                    write!(&mut self.output, "\"^{}\"", rehearsal.name).unwrap();
+                }
+            }
+            for value in note_values(length) {
+                match chord {
+                    [] => self.output.push('z'),
+                    &[spelling] => self.write_pitch(spelling),
+                    chord => {
+                        self.output.push('[');
+                        for &spelling in chord {
+                            self.write_pitch(spelling);
+                        }
+                        self.output.push(']');
+                    }
+                }
+                if value.length() != 1 {
+                    write!(&mut self.output, "{}", value.length()).unwrap();
+                }
+                left -= value.length();
+                if left > 0 && !chord.is_empty() {
+                    self.output.push('-');
+                }
+            }
+            self.measure_left -= length;
+            if self.measure_left == 0 {
+                self.measure_left = self.measure;
+                self.measures += 1;
+                self.accidentals.clear();
+                self.output.push_str(" |");
+                if self.measures.is_multiple_of(CYCLE) {
+                    self.output.push('\n');
+                    continue;
+                }
+            }
+            self.output.push(' ');
+        }
+    }
+}
+
+impl WriteMusic for AbcWriter {
+    fn write_note(&mut self, _: Pitch, event: &Event) {
+        self.write_dynamics(event);
+        self.write_pitches(&event.spell(self.key), event.duration, event.articulation);
+    }
+    fn write_chord(&mut self, _: &[Pitch], event: &Event) {
+        self.write_dynamics(event);
+        self.write_pitches(&event.spell(self.key), event.duration, event.articulation);
+    }
+    fn write_rest(&mut self, duration: u32) {
+        self.write_pitches(&[], duration, None);
+    }
+}
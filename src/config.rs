@@ -0,0 +1,524 @@
+use std::{f64::consts::PI, fmt::Display, str::FromStr};
+
+use rand::{thread_rng, RngCore};
+
+use crate::{
+    Curve, Instrument, Key, MelodicVoice, Overrides, ParseError, Pattern, Progression, Scale,
+    Section, TimeSignature,
+};
+
+/// The lowest and highest pitches which MIDI can represent.
+const MIDI_RANGE: (i32, i32) = (-48, 79);
+
+/// The most voices which can play alongside the melody: each part has its own MIDI channel, and
+/// one of the 16 channels is reserved for percussion.
+const MAX_VOICES: usize = 13;
+
+/// The parameters of a generated piece.
+///
+/// Usually constructed with [`Config::builder`], which validates the parameters.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The harmony preset to use
+    pub harmony: Harmony,
+    /// The rhythm tendency to use
+    pub rhythm: Rhythm,
+    /// The key to transpose the piece into.
+    pub key: Key,
+    /// The time signature of the piece.
+    pub time: TimeSignature,
+    /// The scale which the melody's notes outside of the chords keep to, if any.
+    pub scale: Option<Scale>,
+    /// The chords the accompaniment plays and the melody is drawn towards.
+    pub progression: Progression,
+    /// The number of beats per minute.
+    pub tempo: u32,
+    /// The minimum length (in steps) of notes generated (ignoring stutter).
+    pub min_len: f32,
+    /// The maximum length (in steps) of notes generated (ignoring stutter).
+    pub max_len: f32,
+    /// The pitch of the harmony's lowest note.
+    /// Assumed to be divisible by 12.
+    pub harmony_base: i32,
+    /// The pitch of the melody's center.
+    pub melody_base: i32,
+    /// The lowest pitch of the melody, if it is limited.
+    pub melody_low: Option<i32>,
+    /// The highest pitch of the melody, if it is limited.
+    pub melody_high: Option<i32>,
+    /// The instrument which plays the melody.
+    pub melody_instrument: Instrument,
+    /// The instrument which plays the harmony.
+    pub harmony_instrument: Instrument,
+    /// The melodic voices which play alongside the melody, each on its own staff.
+    pub voices: Vec<MelodicVoice>,
+    /// Scales how frequently the speed of notes changes, in measures.
+    pub steady: f32,
+    /// How strongly the melody oscillates around its center.
+    pub gravity: f32,
+    /// How strongly the melody's velocity declines.
+    pub drag: f32,
+    /// The amount of random influence on the melody.
+    pub nudge: f32,
+    /// The amount of random influence on the speed of notes.
+    pub stutter: f32,
+    /// Whether short notes lead into each phrase, with the phrase rhythm.
+    pub pickups: bool,
+    /// The probability that a measure begins with a motif remembered from the opening of the
+    /// melody, transposed and possibly transformed.
+    pub motifs: f32,
+    /// The probability that a note at least a beat long is followed by a rest. Any rests also
+    /// end each phrase with a breath.
+    pub rests: f32,
+    /// The number of times to repeat the harmony (in each section of the form).
+    pub repeat: u32,
+    /// The sections of the piece, in order, or none for a single section.
+    pub form: Vec<Section>,
+    /// Whether each repetition is a variation, with denser notes, more random motion, and a new
+    /// harmony style.
+    ///
+    /// The variations replace the form when the configuration is built.
+    pub variations: bool,
+    /// The number of measures the accompaniment plays alone before the melody enters.
+    pub intro: u32,
+    /// The number of measures after the repetitions in which the texture thins.
+    pub coda: u32,
+    /// The RNG seed used.
+    pub seed: u64,
+    /// The force to use in direct MIDI output.
+    pub volume: u8,
+    /// Whether to shape the loudness of the piece by the melody's contour, with dynamics marks.
+    pub dynamics: bool,
+    /// Whether to end the piece with a cadence: a calmer melody settling on the tonic over the
+    /// final measures, followed by a held tonic chord.
+    pub ending: bool,
+    /// Whether to slow the tempo of direct MIDI output over the final measures.
+    pub ritardando: bool,
+}
+impl Config {
+    /// The number of measures generated over the progression (including any intro and coda),
+    /// before the final measure of any ending.
+    pub fn measures(&self) -> u32 {
+        let passes = self.sections() * self.repeat;
+        self.intro + passes * self.progression.measures.len() as u32 + self.coda
+    }
+    /// The lowest and highest pitches of the melody, from its instrument unless they are set.
+    pub fn melody_range(&self) -> (i32, i32) {
+        let (low, high) = self.melody_instrument.range();
+        (
+            self.melody_low.unwrap_or(low.max(MIDI_RANGE.0)),
+            self.melody_high.unwrap_or(high.min(MIDI_RANGE.1)),
+        )
+    }
+    /// The number of sections in the form.
+    pub fn sections(&self) -> u32 {
+        self.form.len().max(1) as u32
+    }
+    /// The configuration of a section of the form, with its overrides applied.
+    pub fn section(&self, index: usize) -> Config {
+        match self.form.get(index) {
+            Some(section) => section.overrides.apply(self),
+            None => self.clone(),
+        }
+    }
+    /// Starts building a configuration from the original preset.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            config: Self::version_1(1),
+            seed: None,
+            key: None,
+            melody_base: None,
+        }
+    }
+    fn version_1(repeat: u32) -> Config {
+        Self {
+            harmony: Harmony::Quarter,
+            rhythm: Rhythm::Sinusoidal,
+            key: Key::default(),
+            time: TimeSignature::default(),
+            scale: None,
+            progression: Progression::default(),
+            tempo: 80,
+            min_len: 1.0,
+            max_len: 4.0,
+            harmony_base: -12,
+            melody_base: 12,
+            melody_low: None,
+            melody_high: None,
+            melody_instrument: Instrument::Piano,
+            harmony_instrument: Instrument::Piano,
+            voices: vec![],
+            steady: PI as f32,
+            gravity: 0.15,
+            drag: 0.22,
+            nudge: 1.5,
+            stutter: 0.05,
+            pickups: false,
+            motifs: 0.0,
+            rests: 0.0,
+            repeat,
+            form: vec![],
+            variations: false,
+            intro: 0,
+            coda: 0,
+            seed: 0,
+            volume: 90,
+            dynamics: false,
+            ending: false,
+            ritardando: false,
+        }
+    }
+    fn version_1_1(repeat: u32) -> Config {
+        Self {
+            harmony: Harmony::CenterEighths,
+            min_len: 1.15,
+            max_len: 3.5,
+            ..Self::version_1(repeat)
+        }
+    }
+    fn version_1_2(repeat: u32) -> Config {
+        Self {
+            melody_base: 24,
+            ..Self::version_1_1(repeat)
+        }
+    }
+}
+
+/// Overlays individual parameters onto a preset.
+#[derive(Clone, Debug)]
+pub struct ConfigBuilder {
+    config: Config,
+    seed: Option<u64>,
+    key: Option<Key>,
+    melody_base: Option<i32>,
+}
+macro_rules! setters {
+    ($($field:ident: $ty:ty),*) => {
+        $(
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.config.$field = $field;
+                self
+            }
+        )*
+    };
+}
+impl ConfigBuilder {
+    /// Starts from a named preset, replacing all parameters set so far.
+    ///
+    /// Options: "1", "1.1", "1.2"
+    pub fn preset(self, preset: &str) -> Result<Self, ConfigError> {
+        let repeat = self.config.repeat;
+        let config = match preset {
+            "1" => Config::version_1,
+            "1.1" => Config::version_1_1,
+            "1.2" => Config::version_1_2,
+            _ => return Err(ConfigError::UnknownPreset(preset.to_string())),
+        }(repeat);
+        Ok(Self { config, ..self })
+    }
+    /// Sets the PRNG seed; a random one is chosen otherwise.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+    /// Sets the melody's center; otherwise the preset's is used for the piano, and other
+    /// instruments' melodies are centered in their range.
+    pub fn melody_base(mut self, melody_base: i32) -> Self {
+        self.melody_base = Some(melody_base);
+        self
+    }
+    /// Sets the key; otherwise the piece is in C, major or minor as the progression is written.
+    pub fn key(mut self, key: Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+    setters!(
+        harmony: Harmony,
+        rhythm: Rhythm,
+        time: TimeSignature,
+        scale: Option<Scale>,
+        progression: Progression,
+        tempo: u32,
+        min_len: f32,
+        max_len: f32,
+        harmony_base: i32,
+        melody_low: Option<i32>,
+        melody_high: Option<i32>,
+        melody_instrument: Instrument,
+        harmony_instrument: Instrument,
+        voices: Vec<MelodicVoice>,
+        steady: f32,
+        gravity: f32,
+        drag: f32,
+        nudge: f32,
+        stutter: f32,
+        pickups: bool,
+        motifs: f32,
+        rests: f32,
+        repeat: u32,
+        form: Vec<Section>,
+        variations: bool,
+        intro: u32,
+        coda: u32,
+        volume: u8,
+        dynamics: bool,
+        ending: bool,
+        ritardando: bool
+    );
+    /// Overrides parameters in every section of the form with a name, keeping any overrides
+    /// given before which these leave unset.
+    pub fn section(mut self, name: &str, overrides: Overrides) -> Result<Self, ConfigError> {
+        let mut found = false;
+        for section in self.config.form.iter_mut().filter(|s| s.name == name) {
+            section.overrides = overrides.clone().or(&section.overrides);
+            found = true;
+        }
+        if !found {
+            return Err(ConfigError::UnknownSection(name.to_string()));
+        }
+        Ok(self)
+    }
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let mut config = self.config;
+        config.key = self.key.unwrap_or(Key {
+            minor: config.progression.minor,
+            ..Key::default()
+        });
+        if let Some(melody_base) = self.melody_base {
+            config.melody_base = melody_base;
+        } else if config.melody_instrument != Instrument::Piano {
+            let (low, high) = config.melody_range();
+            config.melody_base = (low + high).div_euclid(2) - config.key.offset();
+        }
+        if config.harmony_base % 12 != 0 {
+            return Err(ConfigError::HarmonyBase(config.harmony_base));
+        }
+        if !(1..=127).contains(&config.volume) {
+            return Err(ConfigError::Volume(config.volume));
+        }
+        if config.tempo == 0 {
+            return Err(ConfigError::Tempo);
+        }
+        if config.voices.len() > MAX_VOICES {
+            return Err(ConfigError::Voices(config.voices.len()));
+        }
+        let sections = (config.form.iter()).map(|section| section.overrides.apply(&config));
+        for section in std::iter::once(config.clone()).chain(sections) {
+            let voices = config.voices.iter().map(|voice| voice.apply(&section));
+            for voice in std::iter::once(section.clone()).chain(voices) {
+                // Notes are moved into the range once the piece is in its key, so the range
+                // itself must also be playable.
+                // The range holds sounding pitches, while the base is moved into the key.
+                let (low, high) = voice.melody_range();
+                let base = voice.melody_base + voice.key.offset();
+                let playable = MIDI_RANGE.0 <= low && high <= MIDI_RANGE.1;
+                if !(low..=high).contains(&base) || high - low < 11 || !playable {
+                    return Err(ConfigError::MelodyRange);
+                }
+            }
+        }
+        if !(0.0..=1.0).contains(&config.motifs) {
+            return Err(ConfigError::Probability("Motif"));
+        }
+        if !(0.0..=1.0).contains(&config.rests) {
+            return Err(ConfigError::Probability("Rest"));
+        }
+        let beats = config.time.pulses().len();
+        if let Some(measure) = (config.progression.measures.iter()).position(|m| m.len() > beats) {
+            return Err(ConfigError::CrowdedMeasure(measure + 1));
+        }
+        if config.variations {
+            if !config.form.is_empty() {
+                return Err(ConfigError::VariationsForm);
+            }
+            config.form = variations(&config);
+            config.repeat = 1;
+        }
+        config.seed = self.seed.unwrap_or_else(|| thread_rng().next_u64());
+        Ok(config)
+    }
+}
+
+/// A section for each repetition: a theme followed by variations.
+///
+/// Over the variations, notes become up to twice as dense and the melody's random motion twice
+/// as strong, while the harmony cycles through every style.
+fn variations(config: &Config) -> Vec<Section> {
+    let last = config.repeat.saturating_sub(1).max(1) as f32;
+    let start = Harmony::ALL
+        .iter()
+        .position(|harmony| *harmony == config.harmony)
+        .unwrap();
+    (0..config.repeat)
+        .map(|i| {
+            let progress = i as f32 / last;
+            let density = 1.0 - 0.5 * progress;
+            let harmony = Harmony::ALL[(start + i as usize) % Harmony::ALL.len()].clone();
+            Section {
+                name: if i == 0 {
+                    "Theme".to_string()
+                } else {
+                    format!("Var. {i}")
+                },
+                vary: false,
+                overrides: Overrides {
+                    harmony: Some(harmony),
+                    min_len: Some(config.min_len * density),
+                    max_len: Some(config.max_len * density),
+                    nudge: Some(config.nudge * (1.0 + progress)),
+                    ..Overrides::default()
+                },
+            }
+        })
+        .collect()
+}
+
+/// A reason a configuration could not be built.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    UnknownPreset(String),
+    UnknownHarmony(String),
+    UnknownRhythm(String),
+    UnknownKey(String),
+    UnknownTime(String),
+    UnknownGround(String),
+    UnknownScale(String),
+    UnknownInstrument(String),
+    Progression(ParseError),
+    CrowdedMeasure(usize),
+    RhythmCurve(String),
+    UnknownSection(String),
+    UnknownOverride(String),
+    VariationsForm,
+    Voices(usize),
+    HarmonyBase(i32),
+    MelodyRange,
+    Volume(u8),
+    Tempo,
+    /// A probability outside of 0 to 1, with the name of the parameter.
+    Probability(&'static str),
+}
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::UnknownPreset(preset) => write!(f, "Unknown preset {preset:?}"),
+            ConfigError::UnknownHarmony(harmony) => write!(f, "Unknown harmony {harmony:?}"),
+            ConfigError::UnknownRhythm(rhythm) => write!(f, "Unknown rhythm {rhythm:?}"),
+            ConfigError::UnknownKey(key) => write!(f, "Unknown key {key:?}"),
+            ConfigError::UnknownTime(time) => write!(f, "Unknown time signature {time:?}"),
+            ConfigError::UnknownGround(ground) => write!(f, "Unknown ground {ground:?}"),
+            ConfigError::UnknownScale(scale) => write!(f, "Unknown scale {scale:?}"),
+            ConfigError::UnknownInstrument(instrument) => {
+                write!(f, "Unknown instrument {instrument:?}")
+            }
+            ConfigError::Progression(error) => write!(f, "Invalid progression: {error}"),
+            ConfigError::CrowdedMeasure(measure) => {
+                write!(
+                    f,
+                    "Measure {measure} of the progression has more chords than beats"
+                )
+            }
+            ConfigError::RhythmCurve(point) => {
+                write!(f, "Invalid rhythm curve point {point:?}")
+            }
+            ConfigError::UnknownSection(name) => write!(f, "Unknown section {name:?}"),
+            ConfigError::UnknownOverride(parameter) => {
+                write!(f, "Unknown parameter {parameter:?}")
+            }
+            ConfigError::Voices(voices) => {
+                write!(f, "At most {MAX_VOICES} voices can be added, not {voices}")
+            }
+            ConfigError::VariationsForm => {
+                write!(f, "Variations cannot be combined with a form")
+            }
+            ConfigError::HarmonyBase(_) => {
+                write!(f, "Harmony can only be adjusted by multiples of 12")
+            }
+            ConfigError::MelodyRange => write!(
+                f,
+                "The range of each melodic voice must include its base (in the piece's key), span at least an octave, and lie between -48 and 79"
+            ),
+            ConfigError::Volume(volume) => {
+                write!(f, "Volume must be between 1 and 127, not {volume}")
+            }
+            ConfigError::Tempo => write!(f, "Tempo must be positive"),
+            ConfigError::Probability(name) => {
+                write!(f, "{name} probability must be between 0 and 1")
+            }
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Harmony {
+    Quarter,
+    UpOctaves,
+    DownOctaves,
+    CenterEighths,
+    Mirror,
+    Triples,
+    QuarterChords,
+}
+impl Harmony {
+    /// Every style, in an order which alternates simpler and busier patterns.
+    pub const ALL: [Harmony; 7] = [
+        Harmony::Quarter,
+        Harmony::CenterEighths,
+        Harmony::QuarterChords,
+        Harmony::UpOctaves,
+        Harmony::Triples,
+        Harmony::DownOctaves,
+        Harmony::Mirror,
+    ];
+}
+impl FromStr for Harmony {
+    type Err = ConfigError;
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        match str {
+            "quarter" => Ok(Harmony::Quarter),
+            "up-octaves" => Ok(Harmony::UpOctaves),
+            "down-octaves" => Ok(Harmony::DownOctaves),
+            "center-8ths" => Ok(Harmony::CenterEighths),
+            "mirror" => Ok(Harmony::Mirror),
+            "triples" => Ok(Harmony::Triples),
+            "quarter-chords" => Ok(Harmony::QuarterChords),
+            _ => Err(ConfigError::UnknownHarmony(str.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Rhythm {
+    Sinusoidal,
+    Sawtooth,
+    /// Alternates linearly between long and short notes.
+    Triangle,
+    /// Alternates abruptly between long and short notes.
+    Square,
+    /// Wanders randomly between long and short notes.
+    RandomWalk,
+    /// Long notes which quickly give way to short notes.
+    Decay,
+    /// Follows the phrases of the harmony, lengthening notes at the end of each.
+    Phrase,
+    /// Follows an envelope over each period.
+    Curve(Curve),
+    /// Begins notes on the onsets of a repeating pattern, ignoring note lengths.
+    Pattern(Pattern),
+}
+impl FromStr for Rhythm {
+    type Err = ConfigError;
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        match str {
+            "sine" | "sinusoidal" => Ok(Rhythm::Sinusoidal),
+            "saw" | "sawtooth" => Ok(Rhythm::Sawtooth),
+            "triangle" => Ok(Rhythm::Triangle),
+            "square" => Ok(Rhythm::Square),
+            "random-walk" | "walk" => Ok(Rhythm::RandomWalk),
+            "decay" => Ok(Rhythm::Decay),
+            "phrase" => Ok(Rhythm::Phrase),
+            _ => str.parse().map(Rhythm::Pattern),
+        }
+    }
+}
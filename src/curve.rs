@@ -0,0 +1,50 @@
+use std::str::FromStr;
+
+use crate::ConfigError;
+
+/// A piecewise-linear envelope over a period of the rhythm.
+///
+/// Each point maps a position in the period (from 0 to 1) to how much longer (up to 1) or
+/// shorter (down to -1) notes should be; positions between points are interpolated.
+#[derive(Clone, Debug)]
+pub struct Curve {
+    /// The points of the curve, by increasing position.
+    pub points: Vec<(f32, f32)>,
+}
+impl Curve {
+    /// The value of the curve at a position in the period.
+    pub fn at(&self, position: f32) -> f32 {
+        let index = self.points.partition_point(|&(x, _)| x <= position);
+        match (
+            self.points.get(index.wrapping_sub(1)),
+            self.points.get(index),
+        ) {
+            (Some(&(x0, y0)), Some(&(x1, y1))) => y0 + (y1 - y0) * (position - x0) / (x1 - x0),
+            (Some(&(_, y)), None) | (None, Some(&(_, y))) => y,
+            (None, None) => 0.0,
+        }
+    }
+}
+impl FromStr for Curve {
+    type Err = ConfigError;
+    /// Parses points such as "0:1,0.5:-1,1:1", separated by commas or whitespace.
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let mut points: Vec<(f32, f32)> = vec![];
+        let separator = |c: char| c == ',' || c.is_whitespace();
+        for point in str.split(separator).filter(|point| !point.is_empty()) {
+            let error = || ConfigError::RhythmCurve(point.to_string());
+            let (x, y) = point.split_once(':').ok_or_else(error)?;
+            let x: f32 = x.parse().map_err(|_| error())?;
+            let y: f32 = y.parse().map_err(|_| error())?;
+            let after = points.last().is_none_or(|&(last, _)| x > last);
+            if !(0.0..=1.0).contains(&x) || !(-1.0..=1.0).contains(&y) || !after {
+                return Err(error());
+            }
+            points.push((x, y));
+        }
+        if points.is_empty() {
+            return Err(ConfigError::RhythmCurve(str.to_string()));
+        }
+        Ok(Self { points })
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{Articulation, Dynamic, Hairpin, Score, CYCLE};
+
+/// The largest change in velocity given to a melody note for its height within its phrase.
+const CONTOUR: f32 = 12.0;
+
+/// Shapes the loudness of a score by the contour of its melody (the first voice).
+///
+/// Each phrase of `CYCLE` measures is given a dynamic level by its average height relative to
+/// the melody's center, so that high phrases are loud and low phrases are soft. The level is
+/// marked at the phrase's first note, with a hairpin towards the next phrase's level, which
+/// every voice's velocities follow. Melody notes are further emphasized by their height
+/// within the phrase, and the first note of a phrase louder than the one before is accented.
+pub(crate) fn write_dynamics(score: &mut Score) {
+    let volume = score.config.volume;
+    let base = score.config.melody_base as f32;
+    let phrase = CYCLE * score.time.measure();
+    // Phrases are counted from the end of any intro, as the harmony's are.
+    let intro = score.config.intro * score.time.measure();
+    let offset = |time: u32| time.saturating_sub(intro);
+    let count = offset(score.length).div_ceil(phrase) as usize;
+    let Some(melody) = score.parts.first().and_then(|part| part.voices.first()) else {
+        return;
+    };
+    // A piece which is only an intro has no phrases to shape.
+    if count == 0 {
+        return;
+    }
+
+    let mut sums = vec![(0.0, 0); count];
+    for event in &melody.events {
+        if let Some(pitch) = event.pitches.first() {
+            let sum = &mut sums[(offset(event.start) / phrase) as usize];
+            sum.0 += (pitch.0 as f32 - base) * event.duration as f32;
+            sum.1 += event.duration;
+        }
+    }
+    let mut heights = vec![];
+    for (sum, duration) in sums {
+        let previous = heights.last().copied().unwrap_or(0.0);
+        heights.push(if duration == 0 {
+            previous
+        } else {
+            sum / duration as f32
+        });
+    }
+    let spread = heights
+        .iter()
+        .fold(1.0, |max: f32, height| max.max(height.abs()));
+    // Phrases range from piano to forte, leaving the extremes for individual notes.
+    let levels: Vec<Dynamic> = heights
+        .iter()
+        .map(|height| Dynamic::ALL[(2.5 + 1.5 * height / spread).round() as usize])
+        .collect();
+    let velocity = |time: u32| {
+        let time = offset(time);
+        let index = (time / phrase) as usize;
+        let from = levels[index].velocity(volume);
+        let to = levels
+            .get(index + 1)
+            .map_or(from, |level| level.velocity(volume));
+        from + (to - from) * (time % phrase) as f32 / phrase as f32
+    };
+
+    for (i, part) in score.parts.iter_mut().enumerate() {
+        for (j, voice) in part.voices.iter_mut().enumerate() {
+            let melody = i == 0 && j == 0;
+            let mut written = None;
+            for event in &mut voice.events {
+                let Some(pitch) = event.pitches.first() else {
+                    continue;
+                };
+                let index = (offset(event.start) / phrase) as usize;
+                let mut target = velocity(event.start);
+                if melody {
+                    let height = pitch.0 as f32 - base - heights[index];
+                    target += (height * 2.0).clamp(-CONTOUR, CONTOUR);
+                    if written.is_none_or(|written| written < index) {
+                        written = Some(index);
+                        if index == 0 || levels[index] != levels[index - 1] {
+                            event.dynamic = Some(levels[index]);
+                        }
+                        if index > 0 && levels[index] > levels[index - 1] {
+                            event.articulation = Some(Articulation::Accent);
+                        }
+                        event.hairpin = match levels.get(index + 1) {
+                            Some(&next) if next > levels[index] => Some(Hairpin::Crescendo),
+                            Some(&next) if next < levels[index] => Some(Hairpin::Decrescendo),
+                            _ => None,
+                        };
+                    }
+                }
+                event.velocity = target.round().clamp(1.0, 127.0) as u8;
+            }
+        }
+    }
+}
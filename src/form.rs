@@ -0,0 +1,172 @@
+use std::str::FromStr;
+
+use crate::{Config, ConfigError, Harmony, Instrument, Rhythm};
+
+/// A section of a piece's form, which lasts for `repeat` passes through the progression.
+#[derive(Clone, Debug)]
+pub struct Section {
+    /// The name of the section, such as "A".
+    pub name: String,
+    /// Whether to generate a new melody, rather than repeating the melody of the first section
+    /// with the same name.
+    pub vary: bool,
+    pub overrides: Overrides,
+}
+impl FromStr for Section {
+    type Err = ConfigError;
+    /// Parses a section name such as "A", followed by `'` for a varied repetition (as in "A'").
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let (name, vary) = match str.strip_suffix('\'') {
+            Some(name) => (name, true),
+            None => (str, false),
+        };
+        if name.is_empty() || !name.chars().all(char::is_alphanumeric) {
+            return Err(ConfigError::UnknownSection(str.to_string()));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            vary,
+            overrides: Overrides::default(),
+        })
+    }
+}
+
+/// A melodic voice which plays alongside the melody, over the same harmony.
+#[derive(Clone, Debug)]
+pub struct MelodicVoice {
+    /// The instrument which plays the voice, limiting it to its range.
+    pub instrument: Instrument,
+    /// Parameters which replace those of the piece (or its sections) in this voice.
+    pub overrides: Overrides,
+}
+impl MelodicVoice {
+    /// The configuration of the voice during a section.
+    pub fn apply(&self, section: &Config) -> Config {
+        let mut config = self.overrides.apply(section);
+        // Voices for instruments other than the piano are centered in their range by default.
+        if self.overrides.melody_base.is_none() && self.instrument != Instrument::Piano {
+            config.melody_base = self.instrument.center() - config.key.offset();
+        }
+        config.melody_instrument = self.instrument;
+        config.melody_low = None;
+        config.melody_high = None;
+        config
+    }
+}
+impl FromStr for MelodicVoice {
+    type Err = ConfigError;
+    /// Parses an instrument, optionally followed by parameters for the voice, such as "violin"
+    /// or "cello:melody-base=-5,rhythm=saw".
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let (instrument, overrides) = match str.split_once(':') {
+            Some((instrument, overrides)) => {
+                // Voices play over the piece's harmony, so they cannot change it.
+                let mut parameters = overrides.split(',').map(str::trim);
+                if let Some(harmony) = parameters.find(|p| p.starts_with("harmony")) {
+                    return Err(ConfigError::UnknownOverride(harmony.to_string()));
+                }
+                (instrument, overrides.parse()?)
+            }
+            None => (str, Overrides::default()),
+        };
+        Ok(Self {
+            instrument: instrument.trim().parse()?,
+            overrides,
+        })
+    }
+}
+
+/// Parameters which replace those of the piece during a section.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub harmony: Option<Harmony>,
+    pub rhythm: Option<Rhythm>,
+    pub min_len: Option<f32>,
+    pub max_len: Option<f32>,
+    pub melody_base: Option<i32>,
+    pub steady: Option<f32>,
+    pub gravity: Option<f32>,
+    pub drag: Option<f32>,
+    pub nudge: Option<f32>,
+    pub stutter: Option<f32>,
+}
+impl Overrides {
+    /// The configuration of a section with these overrides.
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(if let Some($field) = &self.$field {
+                    config.$field = $field.clone();
+                })*
+            };
+        }
+        apply!(
+            harmony,
+            rhythm,
+            min_len,
+            max_len,
+            melody_base,
+            steady,
+            gravity,
+            drag,
+            nudge,
+            stutter
+        );
+        config
+    }
+    /// These overrides, with any parameters they leave unset taken from `other`.
+    pub fn or(self, other: &Overrides) -> Overrides {
+        macro_rules! or {
+            ($($field:ident),*) => {
+                Overrides {
+                    $($field: self.$field.or_else(|| other.$field.clone()),)*
+                }
+            };
+        }
+        or!(
+            harmony,
+            rhythm,
+            min_len,
+            max_len,
+            melody_base,
+            steady,
+            gravity,
+            drag,
+            nudge,
+            stutter
+        )
+    }
+}
+impl FromStr for Overrides {
+    type Err = ConfigError;
+    /// Parses comma-separated parameters named as on the command line, such as
+    /// "rhythm=saw,min-len=2".
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let mut overrides = Self::default();
+        for parameter in str.split(',').map(str::trim) {
+            let error = || ConfigError::UnknownOverride(parameter.to_string());
+            let (name, value) = parameter.split_once('=').ok_or_else(error)?;
+            let value = value.trim();
+            macro_rules! number {
+                ($field:ident) => {
+                    overrides.$field = Some(value.parse().map_err(|_| error())?)
+                };
+            }
+            match name.trim() {
+                "harmony" => overrides.harmony = Some(value.parse()?),
+                "rhythm" => overrides.rhythm = Some(value.parse()?),
+                "min-len" => number!(min_len),
+                "max-len" => number!(max_len),
+                "melody-base" => number!(melody_base),
+                "steady" => number!(steady),
+                "gravity" => number!(gravity),
+                "drag" => number!(drag),
+                "nudge" => number!(nudge),
+                "stutter" => number!(stutter),
+                _ => return Err(error()),
+            }
+        }
+        Ok(overrides)
+    }
+}
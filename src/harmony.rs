@@ -0,0 +1,134 @@
+use crate::{Chord, Config, Harmony, Note, Pitch, Voice, STEP};
+
+/// Divides a beat into eighth notes.
+fn subdivide(beat: u32) -> impl Iterator<Item = u32> {
+    std::iter::repeat_n(STEP / 2, (beat / (STEP / 2)) as usize)
+}
+
+/// Writes the accompaniment, returning the chords it sounds.
+///
+/// Each harmony style is a pattern for every beat of the measure (a quarter note, or a dotted
+/// quarter in compound time), which reproduces the original styles in 4/4.
+pub(crate) fn write_harmony(config: &Config, out: &mut Voice) -> Vec<Chord> {
+    let mut chords = vec![];
+    let note = |pitch, duration| Note {
+        pitch: Pitch(pitch + config.harmony_base),
+        duration,
+    };
+    let pulses = config.time.pulses();
+    let beats = pulses.len();
+    let progression = &config.progression;
+    let len = progression.measures.len() as u32;
+    let passes = config.sections() * config.repeat;
+    let body = config.intro + passes * len;
+    // The intro and coda each play the progression from its first measure.
+    let numbers = (0..config.intro)
+        .chain(0..passes * len)
+        .chain(0..config.coda);
+    let styles: Vec<Harmony> = (0..config.sections() as usize)
+        .map(|i| config.section(i).harmony)
+        .collect();
+    for (number, measure) in numbers.enumerate() {
+        let number = number as u32;
+        // The coda thins the accompaniment, resting on every other beat.
+        let thin = number >= body;
+        // The intro and coda follow the piece's style, rather than the first or last section's.
+        let style = match number.checked_sub(config.intro) {
+            Some(offset) if !thin => &styles[(offset / (config.repeat * len)) as usize],
+            _ => &config.harmony,
+        };
+        let measure = (measure % len) as usize;
+        let chords_in_measure = &progression.measures[measure];
+        let mut eighths = 0;
+        let mut current = None;
+        for (beat, &length) in pulses.iter().enumerate() {
+            let index = progression.chord_index(measure, beat, beats);
+            let progression_chord = &chords_in_measure[index];
+            if current != Some(index) {
+                current = Some(index);
+                chords.push(Chord {
+                    start: out.end(),
+                    duration: 0,
+                    pitches: progression_chord
+                        .pitches
+                        .iter()
+                        .map(|&pitch| Pitch(pitch.0 + config.harmony_base))
+                        .collect(),
+                });
+            }
+            chords.last_mut().unwrap().duration += length;
+            if thin && beat % 2 == 1 {
+                out.rest(length);
+                continue;
+            }
+            let chord = progression_chord.arpeggio();
+            let [p0, p1, p2, p3] = chord;
+            let pitch = chord[beat % 4];
+            let first = beat == 0;
+            let last = beat == beats - 1;
+            match style {
+                Harmony::Quarter => out.note(note(pitch, length), config.volume),
+                Harmony::UpOctaves => {
+                    for (i, eighth) in subdivide(length).enumerate() {
+                        let pitch = [pitch - 12, pitch][i % 2];
+                        out.note(note(pitch, eighth), config.volume);
+                    }
+                }
+                Harmony::DownOctaves => {
+                    for (i, eighth) in subdivide(length).enumerate() {
+                        let pitch = [pitch, pitch - 12][i % 2];
+                        out.note(note(pitch, eighth), config.volume);
+                    }
+                }
+                Harmony::CenterEighths if first => out.note(note(p0, length), config.volume),
+                Harmony::CenterEighths if last => out.note(note(p3, length), config.volume),
+                Harmony::CenterEighths => {
+                    for (i, eighth) in subdivide(length).enumerate() {
+                        out.note(note([p1, p2][i % 2], eighth), config.volume);
+                    }
+                }
+                Harmony::Mirror => {
+                    let harmony = [p0, p0 - 12, p1 - 12, p2 - 12, p3 - 12, p1, p2, p3];
+                    for eighth in subdivide(length) {
+                        out.note(note(harmony[eighths % 8], eighth), config.volume);
+                        eighths += 1;
+                    }
+                }
+                Harmony::Triples if last => out.note(note(p3, length), config.volume),
+                Harmony::Triples => {
+                    let harmony = if beat == beats - 2 {
+                        [note(p1, 1), note(p2, 1), note(p3, 2), note(p2, 2)]
+                    } else {
+                        [note(p0, 1), note(p1, 1), note(p2, 2), note(p1, 2)]
+                    };
+                    let mut left = length;
+                    for note in harmony {
+                        if left == 0 {
+                            break;
+                        }
+                        out.note(note, config.volume);
+                        left -= note.duration;
+                    }
+                }
+                Harmony::QuarterChords => {
+                    let harmony = [[p0, p1, p2], [p0, p1, p3], [p0, p2, p3], [p1, p2, p3]];
+                    let chord = harmony[beat % 4].map(|d| Pitch(d + config.harmony_base));
+                    out.chord(&chord, length, config.volume);
+                }
+            }
+        }
+    }
+    if config.ending {
+        // A tonic chord in the key's mode, held for the final measure
+        let third = if config.key.minor { 3 } else { 4 };
+        let pitches = [0, third, 7, 12].map(|pitch| Pitch(pitch + config.harmony_base));
+        let duration = config.time.measure();
+        chords.push(Chord {
+            start: out.end(),
+            duration,
+            pitches: pitches.to_vec(),
+        });
+        out.chord(&pitches, duration, config.volume);
+    }
+    chords
+}
@@ -0,0 +1,121 @@
+use std::str::FromStr;
+
+use crate::{Clef, ConfigError};
+
+/// An instrument which plays a part, with its range, clef, and MIDI program.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instrument {
+    Piano,
+    Violin,
+    Cello,
+    Flute,
+    /// A clarinet in B-flat, written a whole step above where it sounds.
+    Clarinet,
+    Soprano,
+    Alto,
+    Tenor,
+    Bass,
+    Guitar,
+}
+impl Instrument {
+    /// The name written before the instrument's staff.
+    pub fn to_name(self) -> &'static str {
+        match self {
+            Instrument::Piano => "Piano",
+            Instrument::Violin => "Violin",
+            Instrument::Cello => "Cello",
+            Instrument::Flute => "Flute",
+            Instrument::Clarinet => "Clarinet in B♭",
+            Instrument::Soprano => "Soprano",
+            Instrument::Alto => "Alto",
+            Instrument::Tenor => "Tenor",
+            Instrument::Bass => "Bass",
+            Instrument::Guitar => "Guitar",
+        }
+    }
+    /// The lowest and highest pitches the instrument can comfortably play, as they sound.
+    pub fn range(self) -> (i32, i32) {
+        match self {
+            Instrument::Piano => (-27, 60),
+            Instrument::Violin => (7, 52),
+            Instrument::Cello => (-12, 33),
+            Instrument::Flute => (12, 48),
+            Instrument::Clarinet => (2, 46),
+            Instrument::Soprano => (12, 33),
+            Instrument::Alto => (5, 26),
+            Instrument::Tenor => (0, 21),
+            Instrument::Bass => (-8, 16),
+            Instrument::Guitar => (-8, 35),
+        }
+    }
+    /// The middle of the instrument's range.
+    pub fn center(self) -> i32 {
+        let (low, high) = self.range();
+        (low + high).div_euclid(2)
+    }
+    /// The clef the instrument reads, or none for the piano, which reads either.
+    pub fn clef(self) -> Option<Clef> {
+        match self {
+            Instrument::Piano => None,
+            Instrument::Violin
+            | Instrument::Flute
+            | Instrument::Clarinet
+            | Instrument::Soprano
+            | Instrument::Alto => Some(Clef::Treble),
+            Instrument::Cello | Instrument::Bass => Some(Clef::Bass),
+            Instrument::Tenor | Instrument::Guitar => Some(Clef::Treble8),
+        }
+    }
+    /// The number of letters and half-steps the instrument's music is written above where it
+    /// sounds.
+    pub fn transposition(self) -> (usize, i32) {
+        match self {
+            Instrument::Clarinet => (1, 2),
+            _ => (0, 0),
+        }
+    }
+    /// The General MIDI program (counting from 0) which sounds like the instrument.
+    pub fn program(self) -> u8 {
+        match self {
+            Instrument::Piano => 0,
+            Instrument::Violin => 40,
+            Instrument::Cello => 42,
+            Instrument::Flute => 73,
+            Instrument::Clarinet => 71,
+            Instrument::Soprano | Instrument::Alto | Instrument::Tenor | Instrument::Bass => 52,
+            Instrument::Guitar => 24,
+        }
+    }
+    /// Lilypond's name for the instrument's MIDI program.
+    pub fn to_midi_name(self) -> &'static str {
+        match self {
+            Instrument::Piano => "acoustic grand",
+            Instrument::Violin => "violin",
+            Instrument::Cello => "cello",
+            Instrument::Flute => "flute",
+            Instrument::Clarinet => "clarinet",
+            Instrument::Soprano | Instrument::Alto | Instrument::Tenor | Instrument::Bass => {
+                "choir aahs"
+            }
+            Instrument::Guitar => "acoustic guitar (nylon)",
+        }
+    }
+}
+impl FromStr for Instrument {
+    type Err = ConfigError;
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        match str {
+            "piano" => Ok(Instrument::Piano),
+            "violin" => Ok(Instrument::Violin),
+            "cello" => Ok(Instrument::Cello),
+            "flute" => Ok(Instrument::Flute),
+            "clarinet" => Ok(Instrument::Clarinet),
+            "soprano" => Ok(Instrument::Soprano),
+            "alto" => Ok(Instrument::Alto),
+            "tenor" => Ok(Instrument::Tenor),
+            "bass" => Ok(Instrument::Bass),
+            "guitar" => Ok(Instrument::Guitar),
+            _ => Err(ConfigError::UnknownInstrument(str.to_string())),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    cmp::Ordering,
+    fmt::{Display, Write},
+    str::FromStr,
+};
+
+use crate::{ConfigError, Pitch};
+
+/// The pitch classes of the natural notes C through B.
+const NATURALS: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// The letters of the natural notes, as Lilypond names them.
+const LETTERS: [char; 7] = ['c', 'd', 'e', 'f', 'g', 'a', 'b'];
+
+/// How a pitch is written: a letter name, some number of sharps or flats, and an octave.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Spelling {
+    /// The letter name, from 0 (C) to 6 (B).
+    pub letter: usize,
+    /// The number of half-steps above the natural note (negative for flats).
+    pub alter: i32,
+    /// The scientific octave, in which middle C is C4.
+    pub octave: i32,
+}
+impl Spelling {
+    /// Spells a pitch with a particular letter name.
+    pub fn new(Pitch(pitch): Pitch, letter: usize) -> Self {
+        let alter = (pitch - NATURALS[letter] + 6).rem_euclid(12) - 6;
+        Self {
+            letter,
+            alter,
+            // Pitch 0 is the C below middle C, which is C3.
+            octave: (pitch - alter).div_euclid(12) + 3,
+        }
+    }
+    /// The uppercase letter name.
+    pub fn step(&self) -> char {
+        LETTERS[self.letter].to_ascii_uppercase()
+    }
+    /// The Lilypond name of the pitch class, such as "fis" or "bes".
+    pub fn to_name(self) -> String {
+        let mut name = LETTERS[self.letter].to_string();
+        for _ in 0..self.alter.abs() {
+            name.push_str(if self.alter > 0 { "is" } else { "es" });
+        }
+        name
+    }
+}
+/// Formats the spelling as a Lilypond pitch.
+impl Display for Spelling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_name())?;
+        let octave = self.octave - 3;
+        let adjust_c = if octave >= 0 { '\'' } else { ',' };
+        for _ in 0..octave.abs() {
+            f.write_char(adjust_c)?;
+        }
+        Ok(())
+    }
+}
+
+/// A major or minor key, which the piece is transposed into (C major by default).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Key {
+    /// The letter name of the tonic, from 0 (C) to 6 (B).
+    pub letter: usize,
+    /// The number of half-steps the tonic is above its natural note.
+    pub alter: i32,
+    pub minor: bool,
+}
+impl Key {
+    /// The pitch class of the tonic.
+    pub fn tonic(&self) -> i32 {
+        (NATURALS[self.letter] + self.alter).rem_euclid(12)
+    }
+    /// The number of sharps (or, if negative, flats) in the key signature.
+    pub fn fifths(&self) -> i32 {
+        // Each letter's position in the circle of fifths, relative to C.
+        const FIFTHS: [i32; 7] = [0, 2, 4, -1, 1, 3, 5];
+        FIFTHS[self.letter] + 7 * self.alter - if self.minor { 3 } else { 0 }
+    }
+    /// The number of half-steps the piece is transposed from C, between -5 and 6.
+    pub fn offset(&self) -> i32 {
+        (self.tonic() + 5).rem_euclid(12) - 5
+    }
+    /// The key a number of letters and half-steps above this one.
+    pub fn transpose(&self, letters: usize, half_steps: i32) -> Key {
+        let letter = (self.letter + letters) % 7;
+        Key {
+            letter,
+            alter: (self.tonic() + half_steps - NATURALS[letter] + 6).rem_euclid(12) - 6,
+            minor: self.minor,
+        }
+    }
+    /// Moves a pitch, generated in C major, into this key.
+    ///
+    /// Minor keys lower the third and sixth, but keep the leading tone (as in harmonic minor).
+    pub fn apply(&self, Pitch(pitch): Pitch) -> Pitch {
+        let pitch = match pitch.rem_euclid(12) {
+            4 | 9 if self.minor => pitch - 1,
+            _ => pitch,
+        };
+        Pitch(pitch + self.offset())
+    }
+    /// The half-steps above the tonic of each scale degree.
+    fn scale(&self) -> [i32; 7] {
+        if self.minor {
+            [0, 2, 3, 5, 7, 8, 10]
+        } else {
+            [0, 2, 4, 5, 7, 9, 11]
+        }
+    }
+    /// The number of sharps (or flats) the key signature applies to a letter.
+    pub fn signature(&self, letter: usize) -> i32 {
+        let degree = (letter + 7 - self.letter) % 7;
+        Spelling::new(Pitch(self.tonic() + self.scale()[degree]), letter).alter
+    }
+    /// Chooses the letter name of a pitch.
+    ///
+    /// Notes in the scale are spelled as the key signature does; the others are spelled as
+    /// the most common chromatic alterations of the scale.
+    pub fn spell(&self, pitch: Pitch) -> Spelling {
+        let interval = (pitch.0 - self.tonic()).rem_euclid(12);
+        let degree = match self.scale().iter().position(|&step| step == interval) {
+            Some(degree) => degree,
+            None if self.minor => match interval {
+                // The Neapolitan second, the Picardy third, and the raised fourth, sixth,
+                // and seventh
+                1 => 1,
+                4 => 2,
+                6 => 3,
+                9 => 5,
+                11 => 6,
+                _ => unreachable!(),
+            },
+            None => match interval {
+                // The raised tonic and fourth, and the lowered third, sixth, and seventh
+                1 => 0,
+                3 => 2,
+                6 => 3,
+                8 => 5,
+                10 => 6,
+                _ => unreachable!(),
+            },
+        };
+        Spelling::new(pitch, (self.letter + degree) % 7)
+    }
+    /// Chooses the letter name of a pitch in context.
+    ///
+    /// Tones of the sounding chord are spelled as intervals above its root (so that, for
+    /// example, a D major chord in G-sharp minor is spelled with an F double-sharp). Other notes
+    /// outside the scale are spelled as sharps when the melody rises to the `next` pitch, and
+    /// as flats when it falls.
+    pub fn spell_in(&self, pitch: Pitch, chord: Option<&[Pitch]>, next: Option<Pitch>) -> Spelling {
+        if let Some(chord) = chord.filter(|chord| chord.iter().any(|p| p.note() == pitch.note())) {
+            let root = root(chord);
+            let classes: Vec<i32> = chord
+                .iter()
+                .map(|p| (p.0 - root.0).rem_euclid(12))
+                .collect();
+            let has = |interval| classes.contains(&interval);
+            // The number of letters each interval spans
+            let letters = match (pitch.0 - root.0).rem_euclid(12) {
+                0 => 0,
+                1 | 2 => 1,
+                3 | 4 => 2,
+                5 => 3,
+                6 | 7 => 4,
+                // An augmented fifth, unless there is a perfect fifth as well
+                8 if !has(7) => 4,
+                8 => 5,
+                // A diminished seventh, in a diminished chord
+                9 if has(3) && has(6) && !has(7) => 6,
+                9 => 5,
+                _ => 6,
+            };
+            return Spelling::new(pitch, (self.spell(root).letter + letters) % 7);
+        }
+        let interval = (pitch.0 - self.tonic()).rem_euclid(12);
+        // Only a note between scale degrees a whole step apart takes its letter from the degree
+        // it moves towards. The raised third, sixth, and seventh of a minor key keep theirs.
+        let scale = self.scale();
+        let between = scale.contains(&(interval + 11).rem_euclid(12))
+            && scale.contains(&(interval + 1).rem_euclid(12));
+        let raised = self.minor && matches!(interval, 4 | 9 | 11);
+        if !between || raised {
+            return self.spell(pitch);
+        }
+        match next.map(|next| next.0.cmp(&pitch.0)) {
+            Some(Ordering::Greater) => Spelling::new(pitch, self.spell(Pitch(pitch.0 - 1)).letter),
+            Some(Ordering::Less) => Spelling::new(pitch, self.spell(Pitch(pitch.0 + 1)).letter),
+            _ => self.spell(pitch),
+        }
+    }
+}
+
+/// Guesses the root of a chord: the pitch with the most other tones a third, fifth, or
+/// seventh above it (the lowest, in case of a tie).
+fn root(chord: &[Pitch]) -> Pitch {
+    let mut best = (0, chord[0]);
+    for &root in chord {
+        let count = chord
+            .iter()
+            .filter(|p| [3, 4, 7, 10, 11].contains(&(p.0 - root.0).rem_euclid(12)))
+            .count();
+        if count > best.0 || (count == best.0 && root.0 < best.1 .0) {
+            best = (count, root);
+        }
+    }
+    best.1
+}
+impl FromStr for Key {
+    type Err = ConfigError;
+    /// Parses a Lilypond pitch name, optionally followed by "-major" or "-minor".
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let error = || ConfigError::UnknownKey(str.to_string());
+        let (name, minor) = match str.split_once('-') {
+            Some((name, "major")) => (name, false),
+            Some((name, "minor")) => (name, true),
+            Some(_) => return Err(error()),
+            None => (str, false),
+        };
+        let mut chars = name.chars();
+        let letter = chars
+            .next()
+            .and_then(|letter| LETTERS.iter().position(|&l| l == letter))
+            .ok_or_else(error)?;
+        let mut accidentals = chars.as_str();
+        let mut alter = 0;
+        while !accidentals.is_empty() {
+            if let Some(rest) = accidentals.strip_prefix("is") {
+                alter += 1;
+                accidentals = rest;
+            } else if let Some(rest) = accidentals
+                .strip_prefix("es")
+                .or_else(|| accidentals.strip_prefix('s').filter(|_| alter == 0))
+            {
+                alter -= 1;
+                accidentals = rest;
+            } else {
+                return Err(error());
+            }
+        }
+        let key = Key {
+            letter,
+            alter,
+            minor,
+        };
+        if key.fifths().abs() > 7 {
+            return Err(error());
+        }
+        Ok(key)
+    }
+}
+/// Formats the key as it is parsed, such as "fis-minor".
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tonic = Spelling {
+            letter: self.letter,
+            alter: self.alter,
+            octave: 0,
+        };
+        let mode = if self.minor { "minor" } else { "major" };
+        write!(f, "{}-{mode}", tonic.to_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spells a pitch followed by another, outside of any chord, as Lilypond names it.
+    fn spell(key: &str, pitch: i32, next: i32) -> String {
+        let key: Key = key.parse().unwrap();
+        key.spell_in(Pitch(pitch), None, Some(Pitch(next)))
+            .to_name()
+    }
+
+    #[test]
+    fn chromatic_notes_follow_the_melody() {
+        assert_eq!(spell("c", 6, 7), "fis");
+        assert_eq!(spell("c", 6, 5), "ges");
+        assert_eq!(spell("c-minor", 1, 2), "cis");
+        assert_eq!(spell("c-minor", 1, 0), "des");
+    }
+
+    #[test]
+    fn minor_keys_keep_their_raised_degrees() {
+        assert_eq!(spell("c-minor", 11, 7), "b");
+        assert_eq!(spell("c-minor", 4, 0), "e");
+        assert_eq!(spell("c-minor", 9, 7), "a");
+        assert_eq!(spell("a-minor", 8, 4), "gis");
+        assert_eq!(spell("a-minor", 8, 9), "gis");
+    }
+}
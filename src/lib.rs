@@ -0,0 +1,178 @@
+//! Generates music over a repeating chord progression.
+//!
+//! A piece is described by a [`Config`], usually built with a [`ConfigBuilder`], and generated
+//! into a [`Score`] with [`generate`]. The score can then be rendered by any of the backends:
+//!
+//! ```
+//! let config = passacaglia::Config::builder().seed(5).build().unwrap();
+//! let score = passacaglia::generate(&config);
+//! let lilypond: String = passacaglia::lilypond::render(&score);
+//! let midi: midly::Smf = passacaglia::midi::render(&score);
+//! ```
+
+use rand::SeedableRng;
+
+pub mod abc;
+mod config;
+mod curve;
+mod dynamics;
+mod form;
+mod harmony;
+mod instrument;
+mod key;
+pub mod lilypond;
+mod melody;
+mod meter;
+pub mod midi;
+mod motif;
+pub mod musicxml;
+mod pattern;
+mod pitch;
+mod progression;
+mod scale;
+mod score;
+
+pub use config::{Config, ConfigBuilder, ConfigError, Harmony, Rhythm};
+pub use curve::Curve;
+pub use form::{MelodicVoice, Overrides, Section};
+pub use instrument::Instrument;
+pub use key::{Key, Spelling};
+pub use meter::TimeSignature;
+pub use pattern::Pattern;
+pub use pitch::Pitch;
+pub use progression::{ParseError, Progression, ProgressionChord};
+pub use scale::Scale;
+pub use score::{
+    Articulation, Chord, Clef, Dynamic, Event, Hairpin, Note, Part, Rehearsal, Score, TempoChange,
+    Voice, WriteMusic,
+};
+
+use dynamics::write_dynamics;
+use harmony::write_harmony;
+use melody::write_melody;
+use scale::quantize_melody;
+
+type SeededRng = rand_xoshiro::Xoshiro256StarStar;
+
+/// Generates a piece from its configuration.
+///
+/// The same configuration (including its seed) always results in the same score.
+pub fn generate(config: &Config) -> Score {
+    let rng = &mut SeededRng::seed_from_u64(config.seed);
+    let measure = config.time.measure();
+    let body = config.measures() * measure;
+    let mut harmony = Voice::default();
+    let chords = write_harmony(config, &mut harmony);
+    let sections: Vec<Config> = (0..config.sections() as usize)
+        .map(|i| config.section(i))
+        .collect();
+    let melody = melodic_voice(&sections, &chords, rng);
+    let mut parts = vec![Part {
+        name: "melody".to_string(),
+        clef: config.melody_instrument.clef().unwrap_or(Clef::Treble),
+        instrument: config.melody_instrument,
+        voices: vec![melody],
+    }];
+    // Each further voice draws from its own stream, so adding one leaves the others unchanged.
+    let mut stream = SeededRng::seed_from_u64(config.seed);
+    let mut rngs = vec![];
+    for (i, voice) in config.voices.iter().enumerate() {
+        stream.jump();
+        let mut rng = stream.clone();
+        let sections: Vec<Config> = sections.iter().map(|s| voice.apply(s)).collect();
+        parts.push(Part {
+            name: format!("melody {}", i + 2),
+            clef: voice.instrument.clef().unwrap_or(Clef::Treble),
+            instrument: voice.instrument,
+            voices: vec![melodic_voice(&sections, &chords, &mut rng)],
+        });
+        rngs.push(rng);
+    }
+    parts.push(Part {
+        name: "harmony".to_string(),
+        clef: config.harmony_instrument.clef().unwrap_or(Clef::Bass),
+        instrument: config.harmony_instrument,
+        voices: vec![harmony],
+    });
+    let length = if config.ending { body + measure } else { body };
+    let pass = config.repeat * config.progression.measures.len() as u32 * measure;
+    let rehearsals = (config.form.iter().enumerate())
+        .map(|(i, section)| Rehearsal {
+            start: config.intro * measure + i as u32 * pass,
+            name: section.name.clone() + if section.vary { "'" } else { "" },
+        })
+        .collect();
+    let tempo_changes = if config.ritardando {
+        ritardando(config, body)
+    } else {
+        vec![]
+    };
+    let mut score = Score {
+        config: config.clone(),
+        key: config.key,
+        time: config.time,
+        length,
+        rehearsals,
+        tempo_changes,
+        chords,
+        parts,
+    };
+    if config.dynamics {
+        write_dynamics(&mut score);
+    }
+    score.map_pitches(|pitch| config.progression.apply(config.key, pitch));
+    quantize_melody(&mut score, 0, rng);
+    for (i, rng) in rngs.iter_mut().enumerate() {
+        quantize_melody(&mut score, i + 1, rng);
+    }
+    // Notes just beyond the ideal pitch's limits (such as chord tones) are moved into range.
+    for (i, part) in score.parts.iter_mut().enumerate() {
+        let (low, high) = if i == 0 {
+            config.melody_range()
+        } else {
+            part.instrument.range()
+        };
+        part.voices[0].fold(low, high);
+    }
+    score.respell();
+    score
+}
+
+/// Generates a melodic voice through the sections of the piece, ending it with the piece.
+fn melodic_voice(sections: &[Config], chords: &[Chord], rng: &mut SeededRng) -> Voice {
+    let measure = sections[0].time.measure();
+    let mut voice = Voice::default();
+    let mut state = write_melody(sections, chords, rng, &mut voice);
+    if sections[0].ending {
+        state.land(&mut voice, measure);
+    } else if state.measure_left() != measure {
+        voice.rest(state.measure_left());
+    }
+    voice
+}
+
+/// Slows the tempo beat by beat over the `ENDING` measures before `end`.
+fn ritardando(config: &Config, end: u32) -> Vec<TempoChange> {
+    /// The proportion by which the tempo is reduced by the end.
+    const SLOWING: f32 = 0.4;
+    let start = end.saturating_sub(ENDING * config.time.measure());
+    let beats: Vec<u32> = (start..end)
+        .filter(|&time| config.time.is_beat(time))
+        .collect();
+    (beats.iter().enumerate())
+        .map(|(i, &start)| TempoChange {
+            start,
+            tempo: config.tempo as f32 * (1.0 - SLOWING * (i + 1) as f32 / beats.len() as f32),
+        })
+        .collect()
+}
+
+/// The number of the smallest note generated per beat.
+///
+/// All durations in a [`Score`] are measured in steps.
+pub const STEP: u32 = 4;
+/// The number of measures in a phrase: the original progression's cycle, and the number of
+/// measures written per line.
+pub const CYCLE: u32 = 4;
+/// The number of measures over which an ending (or ritardando) slows down.
+pub const ENDING: u32 = 2;
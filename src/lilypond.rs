@@ -0,0 +1,214 @@
+//! Renders scores as Lilypond source.
+
+use std::fmt::Write;
+
+use crate::{
+    score::{note_values, NoteValue},
+    Event, Hairpin, Instrument, Key, Pitch, Score, Spelling, Voice, WriteMusic, CYCLE,
+};
+
+/// Renders a complete Lilypond file for the score.
+pub fn render(score: &Score) -> String {
+    let config = &score.config;
+    let tempo = config.tempo;
+    let time = score.time;
+    // A piano's parts share a grand staff; other instruments are grouped, each named.
+    let piano = (score.parts.iter()).all(|part| part.instrument == Instrument::Piano);
+    let mut staves = String::new();
+    for (i, part) in score.parts.iter().enumerate() {
+        let name = &part.name;
+        let clef = part.clef.to_name();
+        let (key, voices) = part.written(score.key);
+        let tonic = key.spell(Pitch(key.tonic())).to_name();
+        let mode = if key.minor { "minor" } else { "major" };
+        write!(&mut staves, "\\new Staff = \"{name}\" ").unwrap();
+        if !piano {
+            write!(
+                &mut staves,
+                "\\with {{ instrumentName = \"{}\" midiInstrument = \"{}\" }} ",
+                part.instrument.to_name(),
+                part.instrument.to_midi_name()
+            )
+            .unwrap();
+        }
+        staves.push_str("{\n");
+        let (letters, half_steps) = part.instrument.transposition();
+        if half_steps != 0 {
+            // The pitch which sounds when the instrument reads a middle C
+            let sounding = Spelling::new(Pitch(12 - half_steps), (7 - letters) % 7);
+            writeln!(&mut staves, "\\transposition {sounding}").unwrap();
+        }
+        if i == 0 {
+            writeln!(&mut staves, "\\tempo 4 = {tempo}").unwrap();
+        }
+        writeln!(
+            &mut staves,
+            "\\clef {clef}\n\\key {tonic} \\{mode}\n\\time {time}"
+        )
+        .unwrap();
+        // Rehearsal marks are written above the first staff, in a voice of skips.
+        let marks = i == 0 && !score.rehearsals.is_empty();
+        if marks {
+            staves.push_str("<<\n{ ");
+            let mut time = 0;
+            for rehearsal in &score.rehearsals {
+                if rehearsal.start > time {
+                    write!(&mut staves, "s16*{} ", rehearsal.start - time).unwrap();
+                }
+                write!(&mut staves, "\\mark \\markup \\box \"{}\" ", rehearsal.name).unwrap();
+                time = rehearsal.start;
+            }
+            staves.push_str("}\n");
+        }
+        match &voices[..] {
+            [voice] => staves.push_str(&write_voice(key, score.time.measure(), voice)),
+            voices => {
+                staves.push_str("<<\n");
+                for (i, voice) in voices.iter().enumerate() {
+                    if i > 0 {
+                        staves.push_str("\\\\\n");
+                    }
+                    staves.push_str(&write_voice(key, score.time.measure(), voice));
+                }
+                staves.push_str(">>\n");
+            }
+        }
+        if marks {
+            staves.push_str(">>\n");
+        }
+        staves.push_str("\\fine\n}\n");
+    }
+    let group = if piano { "PianoStaff" } else { "StaffGroup" };
+    format!(
+        r#"\version "2.24.1"
+% generated by passacaglia
+% {config:?}
+\score {{
+\new {group} <<
+{staves}>>
+\layout {{}}
+\midi {{}}
+}}"#
+    )
+}
+
+fn write_voice(key: Key, measure: u32, voice: &Voice) -> String {
+    let mut writer = LilypondWriter::new(key, measure);
+    writer.output = "{ ".to_string();
+    voice.render(&mut writer);
+    writer.push('}');
+    writer.push('\n');
+    writer.output
+}
+
+/// Writes a single (possibly dotted) note value.
+fn write_value(value: NoteValue, out: &mut String) {
+    out.push_str(match value.magnitude {
+        0 => "16",
+        1 => "8",
+        2 => "4",
+        3 => "2",
+        4 => "1",
+        _ => panic!(),
+    });
+    for _ in 0..value.dots {
+        out.push('.');
+    }
+}
+
+/// The articulation and dynamics written after the first note value of an event.
+fn marks(event: &Event) -> String {
+    let mut marks = String::new();
+    if let Some(articulation) = event.articulation {
+        marks.push_str(articulation.to_lilypond());
+    }
+    if let Some(dynamic) = event.dynamic {
+        write!(&mut marks, "\\{}", dynamic.to_name()).unwrap();
+    }
+    match event.hairpin {
+        Some(Hairpin::Crescendo) => marks.push_str("\\<"),
+        Some(Hairpin::Decrescendo) => marks.push_str("\\>"),
+        None => {}
+    }
+    marks
+}
+
+/// Writes the events of a single voice as Lilypond music.
+pub struct LilypondWriter {
+    key: Key,
+    /// The length of a measure, in steps.
+    measure: u32,
+    measure_left: u32,
+    measures: u32,
+    pub output: String,
+}
+impl LilypondWriter {
+    pub fn new(key: Key, measure: u32) -> Self {
+        Self {
+            key,
+            measure,
+            measure_left: measure,
+            measures: 0,
+            output: String::new(),
+        }
+    }
+    fn push(&mut self, ch: char) {
+        self.output.push(ch);
+    }
+    /// Writes a duration as a sequence of dotted notes, splitting it at barlines and joining
+    /// the notes with `join`. The `marks` follow the first note.
+    fn write_duration(&mut self, mut duration: u32, join: &str, marks: &str) {
+        let mut first = true;
+        while duration > 0 {
+            let length = duration.min(self.measure_left);
+            for value in note_values(length) {
+                if !first {
+                    self.output.push_str(join);
+                }
+                write_value(value, &mut self.output);
+                if first {
+                    self.output.push_str(marks);
+                    first = false;
+                }
+            }
+            duration -= length;
+            self.measure_left -= length;
+            if self.measure_left == 0 {
+                self.measure_left = self.measure;
+                self.measures += 1;
+            }
+        }
+    }
+    fn end_event(&mut self) {
+        if self.measure_left == self.measure && self.measures.is_multiple_of(CYCLE) {
+            self.push('\n');
+        } else {
+            self.push(' ');
+        }
+    }
+}
+
+impl WriteMusic for LilypondWriter {
+    fn write_note(&mut self, _: Pitch, event: &Event) {
+        write!(&mut self.output, "{}", event.spell(self.key)[0]).unwrap();
+        self.write_duration(event.duration, "~", &marks(event));
+        self.end_event();
+    }
+    fn write_chord(&mut self, chord: &[Pitch], event: &Event) {
+        self.output.push('<');
+        for (i, spelling) in event.spell(self.key).into_iter().enumerate() {
+            write!(&mut self.output, "{spelling}").unwrap();
+            if i < chord.len() - 1 {
+                self.output.push(' ');
+            }
+        }
+        self.output.push('>');
+        self.write_duration(event.duration, "~", &marks(event));
+        self.end_event();
+    }
+    fn write_rest(&mut self, duration: u32) {
+        self.push('r');
+        self.write_duration(duration, " r", "");
+        self.end_event();
+    }
+}
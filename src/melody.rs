@@ -0,0 +1,450 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+/// The length of the rest before the end of each phrase, in steps.
+const BREATH: u32 = STEP / 2;
+/// How quickly the decay rhythm shortens notes over each period.
+const DECAY: f32 = 4.0;
+
+use crate::{
+    motif::{Motif, MEMORY},
+    score::sounding,
+    Chord, Config, Event, Note, Pitch, Rhythm, SeededRng, Voice, CYCLE, ENDING, STEP,
+};
+
+/// The state of the melody generator between steps.
+pub(crate) struct MelodyState<'a> {
+    pitch: f32,
+    velocity: f32,
+    progress: f32,
+    /// The tendency of the random walk rhythm, from -1 to 1.
+    walk: f32,
+    last_note: u32,
+    time: u32,
+    note: Note,
+    /// Whether the event in progress is a rest.
+    rest: bool,
+    config: &'a Config,
+    /// The chords of the accompaniment, which the melody is drawn towards.
+    chords: &'a [Chord],
+    /// The step at which this state began generating.
+    start: u32,
+    /// The step at which this state stops generating.
+    end: u32,
+    /// The step at which any coda begins.
+    coda: u32,
+    /// The step at which the melody ends, before the final measure of any ending.
+    finish: u32,
+    /// The motifs remembered from the opening of the melody.
+    pub(crate) motifs: Vec<Motif>,
+    /// The notes generated since the start of the current measure.
+    fragment: Vec<Note>,
+    /// The rest of the motif being played, in reverse order, ending with the note in progress.
+    playing: Vec<Note>,
+}
+impl<'a> MelodyState<'a> {
+    /// Starts generating at the `start` step, from the center of the melody.
+    pub(crate) fn new(config: &'a Config, chords: &'a [Chord], start: u32, end: u32) -> Self {
+        let measure = config.time.measure();
+        let finish = config.measures() * measure;
+        MelodyState {
+            pitch: config.melody_base as f32,
+            velocity: 0.0,
+            progress: 0.0,
+            walk: 0.0,
+            last_note: start,
+            time: start,
+            note: Note {
+                pitch: Pitch(config.melody_base),
+                duration: 1,
+            },
+            rest: false,
+            config,
+            chords,
+            start,
+            end,
+            coda: finish - config.coda * measure,
+            finish,
+            motifs: vec![],
+            fragment: vec![],
+            playing: vec![],
+        }
+    }
+    /// Generates the melody up to the end of the state's span, one step at a time.
+    pub(crate) fn run(&mut self, rng: &mut SeededRng, out: &mut Voice) {
+        while self.time < self.end {
+            self.next_note(rng, out);
+        }
+    }
+    /// Ends the note in progress at the end of the state's span.
+    pub(crate) fn close(&mut self, out: &mut Voice) {
+        // The note in progress already includes the step after the last one generated.
+        if self.note.duration > 1 {
+            let note = Note {
+                duration: self.note.duration - 1,
+                ..self.note
+            };
+            if self.rest {
+                out.rest(note.duration);
+            } else {
+                out.note(note, self.config.volume);
+            }
+        }
+        self.note.duration = 1;
+        self.rest = false;
+        self.playing.clear();
+    }
+    pub(crate) fn measure_left(&self) -> u32 {
+        let measure = self.config.time.measure();
+        measure - (self.last_note % measure)
+    }
+    /// How far the melody is through its ending, from 0 (not yet begun) to 1.
+    fn ending(&self) -> f32 {
+        if !self.config.ending {
+            return 0.0;
+        }
+        let measure = self.config.time.measure();
+        let start = self.finish.saturating_sub(ENDING * measure);
+        (self.time.saturating_sub(start) as f32 / (self.finish - start) as f32).min(1.0)
+    }
+    /// The step before which motifs can return: the end of the state's span, or the start of
+    /// any coda or ending.
+    fn limit(&self) -> u32 {
+        let limit = self.end.min(self.coda);
+        if self.config.ending {
+            let measure = self.config.time.measure();
+            limit.min(self.finish.saturating_sub(ENDING * measure))
+        } else {
+            limit
+        }
+    }
+    /// How far a step is through a phrase of the harmony, in steps.
+    fn phrase_offset(&self, time: u32) -> u32 {
+        let measure = self.config.time.measure();
+        time.saturating_sub(self.config.intro * measure) % (CYCLE * measure)
+    }
+    /// The step within a phrase at which any pickup into the next phrase begins: its last beat.
+    fn pickup(&self) -> u32 {
+        let measure = self.config.time.measure();
+        CYCLE * measure - self.config.time.pulses().last().unwrap()
+    }
+    /// Reflects the ideal pitch (and its velocity) off the limits of the melody's range, which
+    /// are sounding pitches, moved back into C as the melody is generated.
+    fn bounce(&mut self) {
+        let offset = self.config.key.offset();
+        let (low, high) = self.config.melody_range();
+        let (low, high) = ((low - offset) as f32, (high - offset) as f32);
+        loop {
+            if self.pitch > high {
+                self.pitch = 2.0 * high - self.pitch;
+            } else if self.pitch < low {
+                self.pitch = 2.0 * low - self.pitch;
+            } else {
+                break;
+            }
+            self.velocity = -self.velocity;
+        }
+    }
+    /// The phrase rhythm's tendency: quicker notes towards the middle of each phrase, then
+    /// longer notes over its last measure, followed by any pickup.
+    fn phrase_rhythm(&self) -> f32 {
+        let measure = self.config.time.measure();
+        let offset = self.phrase_offset(self.time);
+        let cadence = (CYCLE - 1) * measure;
+        if self.config.pickups && offset >= self.pickup() {
+            -1.0
+        } else if offset < cadence {
+            -(PI * offset as f64 / cadence as f64).sin() as f32
+        } else {
+            (2.0 * (offset - cadence) as f32 / measure as f32).min(1.0)
+        }
+    }
+    fn next_note(&mut self, rng: &mut SeededRng, out: &mut Voice) {
+        // Endings calm the melody and draw it towards the tonic nearest its center.
+        let ending = self.ending();
+        let base = self.config.melody_base as f32;
+        let center = base + ((base / 12.0).round() * 12.0 - base) * ending;
+        let nudge = self.config.nudge * (1.0 - 0.5 * ending);
+        let nudge = if rng.gen() { nudge } else { -nudge };
+        let gravity = (self.pitch - center) * -self.config.gravity * (1.0 + 3.0 * ending);
+        let velocity = (self.velocity + gravity) * (1.0 - self.config.drag) + nudge;
+        self.pitch += velocity;
+        self.velocity = velocity;
+        self.bounce();
+        // The ideal pitch keeps moving while a motif plays, so the melody continues from it.
+        if !self.playing.is_empty() {
+            self.play_motif(rng, out);
+            return;
+        }
+
+        let med_len: f32 = (self.config.max_len + self.config.min_len) / 2.0;
+        let dev_len: f32 = (self.config.max_len - self.config.min_len) / 2.0;
+        let measure = self.config.time.measure();
+        let clock = (self.time - self.start) as f64 / measure as f64 / self.config.steady as f64;
+        let position = clock as f32 % 1.0;
+        // Positive increases time to next note; negative decreases it.
+        let add_time = match &self.config.rhythm {
+            Rhythm::Sinusoidal => (clock * 2.0 * PI).cos() as f32,
+            Rhythm::Sawtooth => 1.0 - 2.0 * position,
+            Rhythm::Triangle => 1.0 - 4.0 * position.min(1.0 - position),
+            Rhythm::Square => {
+                if position < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Rhythm::RandomWalk => {
+                // The walk typically crosses its range once per period.
+                let step = 1.0 / (self.config.steady * measure as f32).sqrt();
+                let step = if rng.gen() { step } else { -step };
+                self.walk = (self.walk + step).clamp(-1.0, 1.0);
+                self.walk
+            }
+            Rhythm::Decay => 2.0 * (-DECAY * position).exp() - 1.0,
+            Rhythm::Phrase => self.phrase_rhythm(),
+            Rhythm::Curve(curve) => curve.at(position),
+            // Patterns choose when notes begin directly.
+            Rhythm::Pattern(_) => 0.0,
+        };
+        let mut speed = 1.0 / (dev_len * add_time + med_len) * (1.0 - 0.5 * ending);
+        // The coda thins the melody along with the accompaniment.
+        if self.time >= self.coda {
+            speed *= 0.5;
+        }
+        // A pickup begins with a new note.
+        if let Rhythm::Phrase = self.config.rhythm {
+            if self.config.pickups && self.phrase_offset(self.time + 1) == self.pickup() {
+                self.progress = self.progress.max(1.0);
+            }
+        }
+        self.progress += speed;
+        self.time += 1;
+        // With any rests, the melody also breathes before the end of each phrase.
+        if self.config.rests > 0.0 {
+            let breath = CYCLE * measure - BREATH;
+            let offset = self.phrase_offset(self.time);
+            if !self.rest && offset == breath {
+                self.write_note(out);
+                self.last_note = self.time;
+                self.note.duration = 1;
+                self.rest = true;
+                return;
+            }
+            // No note begins until the breath ends with the phrase.
+            if self.rest && offset > breath {
+                self.note.duration += 1;
+                return;
+            }
+            if self.rest && offset == 0 {
+                self.write_note(out);
+                self.progress = 0.0;
+                self.begin_note(rng);
+                return;
+            }
+        }
+        let due = match &self.config.rhythm {
+            Rhythm::Pattern(pattern) => pattern.is_onset(self.time - self.start),
+            _ => self.progress > 1.0,
+        };
+        if (due || rng.gen::<f32>() < self.config.stutter) && rng.gen::<f32>() > self.config.stutter
+        {
+            self.progress -= 1.0;
+            self.write_note(out);
+            self.begin_note(rng);
+        } else {
+            self.note.duration += 1;
+        }
+    }
+    /// Writes the note (or rest) in progress.
+    fn write_note(&mut self, out: &mut Voice) {
+        if self.rest {
+            out.rest(self.note.duration);
+            // Motifs are only remembered from unbroken melody.
+            self.fragment.clear();
+        } else {
+            out.note(self.note, self.config.volume);
+            if self.motifs.len() < MEMORY {
+                self.fragment.push(self.note);
+            }
+        }
+    }
+    /// Chooses the note beginning at the current step.
+    ///
+    /// A note at least a beat long may be followed by a rest instead. The first note of each
+    /// measure completes the fragment remembered from the last measure, and may begin a
+    /// remembered motif instead.
+    fn begin_note(&mut self, rng: &mut SeededRng) {
+        let measure = self.config.time.measure();
+        let new_measure = self.time / measure != self.last_note / measure;
+        let long = !self.rest && self.note.duration >= STEP;
+        self.last_note = self.time;
+        self.rest = false;
+        let mut pitch = Pitch(self.pitch.round() as i32);
+        if new_measure {
+            let fragment = std::mem::take(&mut self.fragment);
+            if self.motifs.len() < MEMORY {
+                self.motifs.extend(Motif::new(&fragment));
+            }
+        }
+        if long && self.config.rests > 0.0 && rng.gen::<f32>() < self.config.rests {
+            self.note.duration = 1;
+            self.rest = true;
+            return;
+        }
+        if new_measure && self.recall(rng, pitch) {
+            return;
+        }
+        // Notes just before a beat keep the ideal pitch.
+        if !self.config.time.is_beat(self.last_note + 1) {
+            if let Some(chord) = sounding(self.chords, self.time) {
+                pitch = pitch.nearest_note(rng, &chord.pitches);
+            }
+        }
+        self.note = Note { pitch, duration: 1 };
+    }
+    /// Begins a remembered motif at the current step, if one is chosen and it ends before the
+    /// limit.
+    ///
+    /// The motif is transposed to begin on a tone of the chord, with its range centered on the
+    /// ideal pitch.
+    fn recall(&mut self, rng: &mut SeededRng, pitch: Pitch) -> bool {
+        if self.motifs.is_empty() || self.config.motifs <= 0.0 {
+            return false;
+        }
+        if rng.gen::<f32>() >= self.config.motifs {
+            return false;
+        }
+        let motif = self.motifs[rng.gen_range(0..self.motifs.len())].transform(rng);
+        if self.time + motif.duration() > self.limit() {
+            return false;
+        }
+        let start = match sounding(self.chords, self.time) {
+            Some(chord) => Pitch(pitch.0 - motif.center()).nearest_note(rng, &chord.pitches),
+            None => Pitch(pitch.0 - motif.center()),
+        };
+        self.playing = motif.notes(start).collect();
+        self.playing.reverse();
+        let first = self.playing[self.playing.len() - 1];
+        self.note = Note {
+            duration: 1,
+            ..first
+        };
+        true
+    }
+    /// Continues the motif being played, returning to generated notes when it ends.
+    fn play_motif(&mut self, rng: &mut SeededRng, out: &mut Voice) {
+        self.time += 1;
+        if self.note.duration < self.playing[self.playing.len() - 1].duration {
+            self.note.duration += 1;
+            return;
+        }
+        out.note(self.note, self.config.volume);
+        self.playing.pop();
+        match self.playing.last() {
+            Some(&note) => {
+                self.last_note = self.time;
+                self.note = Note {
+                    duration: 1,
+                    ..note
+                };
+            }
+            None => {
+                self.progress = 0.0;
+                self.begin_note(rng);
+            }
+        }
+    }
+    /// Ends the melody with the note in progress, followed by the tonic nearest the ideal pitch
+    /// held for `duration` steps.
+    pub(crate) fn land(&mut self, out: &mut Voice, duration: u32) {
+        self.close(out);
+        let pitch = Pitch((self.pitch / 12.0).round() as i32 * 12);
+        out.note(Note { pitch, duration }, self.config.volume);
+    }
+}
+
+/// Writes the melody of every section, up to the end of any coda, returning the state of the
+/// last melody generated.
+///
+/// Sections repeating an earlier section's name copy its melody, unless they are varied.
+pub(crate) fn write_melody<'a>(
+    sections: &'a [Config],
+    chords: &'a [Chord],
+    rng: &mut SeededRng,
+    out: &mut Voice,
+) -> MelodyState<'a> {
+    let config = &sections[0];
+    let measure = config.time.measure();
+    let length = config.repeat * config.progression.measures.len() as u32 * measure;
+    let start = config.intro * measure;
+    let finish = config.measures() * measure;
+    if start > 0 {
+        out.rest(start);
+    }
+    let mut melodies: Vec<(&str, u32, Vec<Event>)> = vec![];
+    let mut motifs = vec![];
+    let mut state = None;
+    for (i, section) in sections.iter().enumerate() {
+        let section_start = start + i as u32 * length;
+        let last = i == sections.len() - 1;
+        let (name, vary) = config
+            .form
+            .get(i)
+            .map_or(("", false), |section| (&section.name[..], section.vary));
+        let earlier = melodies.iter().find(|(earlier, ..)| *earlier == name);
+        if let Some((_, earlier_start, events)) = earlier.filter(|_| !vary) {
+            // Any ending is generated anew, so that the melody can settle on the tonic.
+            let cut = if config.ending {
+                finish.saturating_sub(ENDING * measure)
+            } else {
+                u32::MAX
+            };
+            for event in events {
+                let start = event.start - earlier_start + section_start;
+                if start >= cut {
+                    break;
+                }
+                out.events.push(Event {
+                    start,
+                    duration: event.duration.min(cut - start),
+                    ..event.clone()
+                });
+            }
+            state = None;
+            continue;
+        }
+        // The last section continues through the coda.
+        let end = if last { finish } else { section_start + length };
+        let first = out.events.len();
+        let mut section_state = MelodyState::new(section, chords, section_start, end);
+        // Motifs remembered in earlier sections can return in later ones.
+        section_state.motifs = std::mem::take(&mut motifs);
+        section_state.run(rng, out);
+        motifs.clone_from(&section_state.motifs);
+        if !last {
+            section_state.close(out);
+        }
+        if earlier.is_none() {
+            melodies.push((name, section_start, out.events[first..].to_vec()));
+        }
+        state = Some(section_state);
+    }
+    // A repeated last section is followed by a new melody for any coda or ending.
+    state.unwrap_or_else(|| {
+        let section_start = out.end();
+        let last = out
+            .events
+            .iter()
+            .rev()
+            .find_map(|event| event.pitches.first());
+        let mut state = MelodyState::new(config, chords, section_start, finish);
+        if let Some(&pitch) = last {
+            state.pitch = pitch.0 as f32;
+            state.note.pitch = pitch;
+        }
+        state.run(rng, out);
+        state
+    })
+}
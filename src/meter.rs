@@ -0,0 +1,80 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{ConfigError, STEP};
+
+/// A time signature over quarter or eighth notes (4/4 by default).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimeSignature {
+    /// The number of quarter or eighth notes per measure.
+    pub beats: u32,
+    /// The note value counted by `beats`: either 4 or 8.
+    pub unit: u32,
+}
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { beats: 4, unit: 4 }
+    }
+}
+impl TimeSignature {
+    /// The length of a measure, in steps.
+    pub fn measure(&self) -> u32 {
+        self.beats * STEP * 4 / self.unit
+    }
+    /// The lengths (in steps) of the beats in a measure.
+    ///
+    /// Eighth notes are grouped in threes when they can be, as in 6/8 or 9/8, and otherwise in
+    /// twos with a final three, as in 7/8 (2+2+3).
+    pub fn pulses(&self) -> Vec<u32> {
+        let eighth = STEP / 2;
+        match self.unit {
+            4 => vec![STEP; self.beats as usize],
+            _ if self.beats.is_multiple_of(3) => vec![3 * eighth; self.beats as usize / 3],
+            _ if self.beats.is_multiple_of(2) => vec![2 * eighth; self.beats as usize / 2],
+            _ => {
+                let mut pulses = vec![2 * eighth; self.beats as usize / 2 - 1];
+                pulses.push(3 * eighth);
+                pulses
+            }
+        }
+    }
+    /// Whether a beat begins at a step.
+    pub fn is_beat(&self, time: u32) -> bool {
+        let mut offset = time % self.measure();
+        for pulse in self.pulses() {
+            if offset == 0 {
+                return true;
+            } else if offset < pulse {
+                return false;
+            }
+            offset -= pulse;
+        }
+        false
+    }
+}
+impl FromStr for TimeSignature {
+    type Err = ConfigError;
+    /// Parses a time signature such as "3/4" or "7/8".
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let error = || ConfigError::UnknownTime(str.to_string());
+        let (beats, unit) = str.split_once('/').ok_or_else(error)?;
+        let time = TimeSignature {
+            beats: beats.parse().map_err(|_| error())?,
+            unit: unit.parse().map_err(|_| error())?,
+        };
+        // Measures must be shorter than a breve, which is not written by any backend.
+        let valid = match time.unit {
+            4 => (1..8).contains(&time.beats),
+            8 => (3..16).contains(&time.beats),
+            _ => false,
+        };
+        if !valid {
+            return Err(error());
+        }
+        Ok(time)
+    }
+}
+impl Display for TimeSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.beats, self.unit)
+    }
+}
@@ -0,0 +1,162 @@
+//! Renders scores as standard MIDI files.
+
+use midly::{
+    num::{u24, u28, u4, u7},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
+
+use crate::{Articulation, Event, Part, Pitch, Score, WriteMusic, STEP};
+
+/// The channel which General MIDI reserves for percussion.
+const PERCUSSION: u8 = 9;
+
+/// Renders the score with one track per voice, after a control track.
+///
+/// Each part plays on its own channel, with its instrument's program.
+pub fn render(score: &Score) -> Smf<'_> {
+    let mut tracks = vec![];
+    let channels = (0..16).filter(|&channel| channel != PERCUSSION);
+    for (part, channel) in score.parts.iter().zip(channels) {
+        for voice in &part.voices {
+            let mut writer = MidiWriter::new(part, channel);
+            voice.render(&mut writer);
+            tracks.push(writer.output);
+        }
+    }
+    make_midi(score, tracks)
+}
+
+/// Writes the events of a single voice as a MIDI track.
+pub struct MidiWriter<'a> {
+    channel: u4,
+    /// The time since the last event written, for rests and shortened notes.
+    delay: u32,
+    pub output: Track<'a>,
+}
+impl<'a> MidiWriter<'a> {
+    pub fn new(part: &'a Part, channel: u8) -> Self {
+        let channel = u4::new(channel);
+        MidiWriter {
+            channel,
+            delay: 0,
+            output: vec![
+                TrackEvent {
+                    delta: u28::new(0),
+                    kind: TrackEventKind::Meta(MetaMessage::TrackName(part.name.as_bytes())),
+                },
+                TrackEvent {
+                    delta: u28::new(0),
+                    kind: TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::ProgramChange {
+                            program: u7::new(part.instrument.program()),
+                        },
+                    },
+                },
+            ],
+        }
+    }
+    fn write_pitches(&mut self, chord: &[Pitch], event: &Event) {
+        let vel = match event.articulation {
+            Some(Articulation::Accent) => event.velocity.saturating_add(20).min(127),
+            None => event.velocity,
+        };
+        let mut delta = Some(u28::new(self.delay));
+        for &pitch in chord {
+            let delta = delta.take().unwrap_or(u28::new(0));
+            self.output.push(TrackEvent {
+                delta,
+                kind: TrackEventKind::Midi {
+                    channel: self.channel,
+                    message: MidiMessage::NoteOn {
+                        key: pitch_to_midi(pitch),
+                        vel: u7::new(vel),
+                    },
+                },
+            });
+        }
+        let mut delta = Some(u28::new(event.duration));
+        for &pitch in chord {
+            let delta = delta.take().unwrap_or(u28::new(0));
+            self.output.push(TrackEvent {
+                delta,
+                kind: TrackEventKind::Midi {
+                    channel: self.channel,
+                    message: MidiMessage::NoteOn {
+                        key: pitch_to_midi(pitch),
+                        vel: u7::new(0),
+                    },
+                },
+            });
+        }
+        self.delay = 0;
+    }
+}
+fn make_midi<'a>(score: &Score, mut tracks: Vec<Track<'a>>) -> Smf<'a> {
+    let mut control = vec![
+        TrackEvent {
+            delta: u28::new(0),
+            // Represents a time signature beats/unit, where the unit is a power of 2
+            // 24 times 1/24 of a quarter note is one quarter note; one beat is a metronome tick
+            // 8 is the number of 32nd notes per quarter
+            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(
+                score.time.beats as u8,
+                score.time.unit.ilog2() as u8,
+                (score.time.pulses()[0] * 24 / STEP) as u8,
+                8,
+            )),
+        },
+        TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::KeySignature(
+                score.key.fifths() as i8,
+                score.key.minor,
+            )),
+        },
+        TrackEvent {
+            delta: u28::new(0),
+            // microseconds/beat
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(
+                60_000_000 / score.config.tempo,
+            ))),
+        },
+    ];
+    let mut time = 0;
+    for change in &score.tempo_changes {
+        control.push(TrackEvent {
+            delta: u28::new(change.start - time),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(
+                (60_000_000.0 / change.tempo) as u32,
+            ))),
+        });
+        time = change.start;
+    }
+    control.push(TrackEvent {
+        delta: u28::new(score.length - time),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    tracks.insert(0, control);
+    Smf {
+        header: Header {
+            format: Format::Parallel,
+            timing: Timing::Metrical((STEP as u16).into()),
+        },
+        tracks,
+    }
+}
+fn pitch_to_midi(Pitch(pitch): Pitch) -> u7 {
+    const MIDDLE_C: i32 = 48;
+    u7::new((pitch + MIDDLE_C).try_into().unwrap())
+}
+
+impl WriteMusic for MidiWriter<'_> {
+    fn write_note(&mut self, pitch: Pitch, event: &Event) {
+        self.write_pitches(&[pitch], event);
+    }
+    fn write_chord(&mut self, chord: &[Pitch], event: &Event) {
+        self.write_pitches(chord, event);
+    }
+    fn write_rest(&mut self, duration: u32) {
+        self.delay += duration;
+    }
+}
@@ -0,0 +1,69 @@
+use rand::Rng;
+
+use crate::{Note, Pitch, SeededRng};
+
+/// The number of motifs remembered from the opening of the melody.
+pub(crate) const MEMORY: usize = 4;
+
+/// The probability of each transformation applied when a motif returns.
+const TRANSFORM: f64 = 0.25;
+
+/// A short fragment of generated melody: a rhythm and a pattern of intervals.
+#[derive(Clone, Debug)]
+pub(crate) struct Motif {
+    /// The notes of the motif, with pitches relative to its first note.
+    notes: Vec<Note>,
+}
+impl Motif {
+    /// Remembers a fragment of melody, if it has enough notes to be recognizable.
+    pub(crate) fn new(fragment: &[Note]) -> Option<Self> {
+        let first = fragment.first()?.pitch.0;
+        let notes = fragment
+            .iter()
+            .map(|note| Note {
+                pitch: Pitch(note.pitch.0 - first),
+                ..*note
+            })
+            .collect();
+        (fragment.len() >= 2).then_some(Self { notes })
+    }
+    /// The motif, possibly inverted, played backwards, or in notes twice as long.
+    pub(crate) fn transform(&self, rng: &mut SeededRng) -> Self {
+        let mut notes = self.notes.clone();
+        if rng.gen_bool(TRANSFORM) {
+            for note in &mut notes {
+                note.pitch.0 = -note.pitch.0;
+            }
+        }
+        if rng.gen_bool(TRANSFORM) {
+            notes.reverse();
+            let first = notes[0].pitch.0;
+            for note in &mut notes {
+                note.pitch.0 -= first;
+            }
+        }
+        if rng.gen_bool(TRANSFORM) {
+            for note in &mut notes {
+                note.duration *= 2;
+            }
+        }
+        Self { notes }
+    }
+    /// The middle of the motif's range, relative to its first note.
+    pub(crate) fn center(&self) -> i32 {
+        let pitches = self.notes.iter().map(|note| note.pitch.0);
+        let (low, high) = (pitches.clone().min().unwrap(), pitches.max().unwrap());
+        (low + high).div_euclid(2)
+    }
+    /// The length of the motif, in steps.
+    pub(crate) fn duration(&self) -> u32 {
+        self.notes.iter().map(|note| note.duration).sum()
+    }
+    /// The notes of the motif transposed to begin on a pitch.
+    pub(crate) fn notes(&self, pitch: Pitch) -> impl Iterator<Item = Note> + '_ {
+        self.notes.iter().map(move |note| Note {
+            pitch: Pitch(pitch.0 + note.pitch.0),
+            ..*note
+        })
+    }
+}
@@ -0,0 +1,372 @@
+//! Renders scores as MusicXML (partwise, version 4.0) documents.
+//!
+//! A piano score becomes a single part, with one staff per part of the score; otherwise each
+//! part of the score becomes a part of its own.
+
+use std::fmt::Write;
+
+use crate::{
+    score::{note_values, NoteValue},
+    Articulation, Clef, Event, Hairpin, Instrument, Key, Part, Pitch, Score, Spelling, WriteMusic,
+    STEP,
+};
+
+/// Renders a complete MusicXML document for the score.
+pub fn render(score: &Score) -> String {
+    let piano = (score.parts.iter()).all(|part| part.instrument == Instrument::Piano);
+    let groups: Vec<&[Part]> = if piano {
+        vec![&score.parts]
+    } else {
+        score.parts.chunks(1).collect()
+    };
+
+    let mut out = String::new();
+    out.push_str(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#,
+        "\n",
+        r#"<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">"#,
+        "\n",
+        r#"<score-partwise version="4.0">"#,
+        "\n",
+    ));
+    writeln!(
+        out,
+        "<identification>\n<encoding>\n<software>passacaglia {}</software>\n</encoding>",
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<miscellaneous>\n<miscellaneous-field name=\"config\">{}</miscellaneous-field>\n</miscellaneous>\n</identification>",
+        escape(&format!("{:?}", score.config))
+    )
+    .unwrap();
+    out.push_str("<part-list>\n");
+    for (i, group) in groups.iter().enumerate() {
+        let instrument = group[0].instrument;
+        let id = format!("P{}", i + 1);
+        writeln!(
+            out,
+            "<score-part id=\"{id}\">\n<part-name>{}</part-name>\n<score-instrument id=\"{id}-I1\">\n<instrument-name>{0}</instrument-name>\n</score-instrument>",
+            instrument.to_name()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "<midi-instrument id=\"{id}-I1\">\n<midi-channel>{}</midi-channel>\n<midi-program>{}</midi-program>\n</midi-instrument>\n</score-part>",
+            // Skipping the percussion channel, as the MIDI renderer does
+            if i < 9 { i + 1 } else { i + 2 },
+            instrument.program() + 1
+        )
+        .unwrap();
+    }
+    out.push_str("</part-list>\n");
+    for (i, group) in groups.iter().enumerate() {
+        write_part(score, i, group, &mut out);
+    }
+    out.push_str("</score-partwise>\n");
+    out
+}
+
+/// Writes one part of the document, with a staff for each of the score's `parts`.
+fn write_part(score: &Score, index: usize, parts: &[Part], out: &mut String) {
+    let measure = score.time.measure();
+    let count = score.length.div_ceil(measure);
+    let key = parts[0].written(score.key).0;
+    let mut voices = vec![];
+    for (staff, part) in parts.iter().enumerate() {
+        let (key, written) = part.written(score.key);
+        for (voice, events) in written.iter().enumerate() {
+            let mut writer = MusicXmlWriter::new(key, measure, staff + 1, staff * 4 + voice + 1);
+            events.render(&mut writer);
+            voices.push(writer.finish(count));
+        }
+    }
+    writeln!(out, "<part id=\"P{}\">", index + 1).unwrap();
+    for number in 0..count as usize {
+        writeln!(out, "<measure number=\"{}\">", number + 1).unwrap();
+        if number == 0 {
+            write_attributes(score, key, parts, out);
+            if index == 0 {
+                write_tempo(score, out);
+            }
+        }
+        let start = number as u32 * measure;
+        let rehearsals = score
+            .rehearsals
+            .iter()
+            .filter(|r| index == 0 && r.start == start);
+        for rehearsal in rehearsals {
+            writeln!(
+                out,
+                "<direction placement=\"above\">\n<direction-type>\n<rehearsal>{}</rehearsal>\n</direction-type>\n<staff>1</staff>\n</direction>",
+                escape(&rehearsal.name)
+            )
+            .unwrap();
+        }
+        for (i, voice) in voices.iter().enumerate() {
+            if i > 0 {
+                writeln!(out, "<backup>\n<duration>{measure}</duration>\n</backup>").unwrap();
+            }
+            out.push_str(&voice[number]);
+        }
+        if number == count as usize - 1 {
+            out.push_str(
+                "<barline location=\"right\">\n<bar-style>light-heavy</bar-style>\n</barline>\n",
+            );
+        }
+        out.push_str("</measure>\n");
+    }
+    out.push_str("</part>\n");
+}
+
+fn write_attributes(score: &Score, key: Key, parts: &[Part], out: &mut String) {
+    writeln!(out, "<attributes>\n<divisions>{STEP}</divisions>").unwrap();
+    let mode = if key.minor { "minor" } else { "major" };
+    writeln!(
+        out,
+        "<key>\n<fifths>{}</fifths>\n<mode>{mode}</mode>\n</key>",
+        key.fifths()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<time>\n<beats>{}</beats>\n<beat-type>{}</beat-type>\n</time>",
+        score.time.beats, score.time.unit
+    )
+    .unwrap();
+    writeln!(out, "<staves>{}</staves>", parts.len()).unwrap();
+    for (staff, part) in parts.iter().enumerate() {
+        let (sign, line) = match part.clef {
+            Clef::Treble | Clef::Treble8 => ('G', 2),
+            Clef::Bass => ('F', 4),
+        };
+        write!(
+            out,
+            "<clef number=\"{}\">\n<sign>{sign}</sign>\n<line>{line}</line>\n",
+            staff + 1
+        )
+        .unwrap();
+        if part.clef == Clef::Treble8 {
+            out.push_str("<clef-octave-change>-1</clef-octave-change>\n");
+        }
+        out.push_str("</clef>\n");
+    }
+    let (letters, half_steps) = parts[0].instrument.transposition();
+    if half_steps != 0 {
+        writeln!(
+            out,
+            "<transpose>\n<diatonic>{}</diatonic>\n<chromatic>{}</chromatic>\n</transpose>",
+            -(letters as i32),
+            -half_steps
+        )
+        .unwrap();
+    }
+    out.push_str("</attributes>\n");
+}
+
+fn write_tempo(score: &Score, out: &mut String) {
+    let tempo = score.config.tempo;
+    writeln!(
+        out,
+        "<direction placement=\"above\">\n<direction-type>\n<metronome>\n<beat-unit>quarter</beat-unit>\n<per-minute>{tempo}</per-minute>\n</metronome>\n</direction-type>\n<staff>1</staff>\n<sound tempo=\"{tempo}\"/>\n</direction>"
+    )
+    .unwrap();
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Writes the events of a single voice as MusicXML notes, split into measures.
+pub struct MusicXmlWriter {
+    key: Key,
+    /// The length of a measure, in steps.
+    measure: u32,
+    staff: usize,
+    voice: usize,
+    measure_left: u32,
+    /// Whether a hairpin has begun but not yet ended.
+    wedge: bool,
+    /// The completed measures, followed by the one being written.
+    pub measures: Vec<String>,
+}
+impl MusicXmlWriter {
+    pub fn new(key: Key, measure: u32, staff: usize, voice: usize) -> Self {
+        Self {
+            key,
+            staff,
+            voice,
+            measure,
+            measure_left: measure,
+            wedge: false,
+            measures: vec![String::new()],
+        }
+    }
+    /// Fills the voice with rests up to `count` measures, returning their contents.
+    pub fn finish(mut self, count: u32) -> Vec<String> {
+        if self.measure_left != self.measure {
+            self.write_rest(self.measure_left);
+        }
+        self.measures.pop();
+        while self.measures.len() < count as usize {
+            self.measures.push(format!(
+                "<note>\n<rest measure=\"yes\"/>\n<duration>{}</duration>\n<voice>{}</voice>\n<staff>{}</staff>\n</note>\n",
+                self.measure,
+                self.voice,
+                self.staff
+            ));
+        }
+        self.measures
+    }
+    /// Writes the dynamics marks beginning at an event, ending any hairpin they interrupt.
+    fn write_dynamics(&mut self, event: &Event) {
+        if event.dynamic.is_none() && event.hairpin.is_none() {
+            return;
+        }
+        let out = self.measures.last_mut().unwrap();
+        out.push_str("<direction placement=\"below\">\n");
+        if std::mem::take(&mut self.wedge) {
+            out.push_str("<direction-type>\n<wedge type=\"stop\"/>\n</direction-type>\n");
+        }
+        if let Some(dynamic) = event.dynamic {
+            writeln!(
+                out,
+                "<direction-type>\n<dynamics>\n<{}/>\n</dynamics>\n</direction-type>",
+                dynamic.to_name()
+            )
+            .unwrap();
+        }
+        if let Some(hairpin) = event.hairpin {
+            let kind = match hairpin {
+                Hairpin::Crescendo => "crescendo",
+                Hairpin::Decrescendo => "diminuendo",
+            };
+            writeln!(
+                out,
+                "<direction-type>\n<wedge type=\"{kind}\"/>\n</direction-type>"
+            )
+            .unwrap();
+            self.wedge = true;
+        }
+        writeln!(
+            out,
+            "<voice>{}</voice>\n<staff>{}</staff>\n</direction>",
+            self.voice, self.staff
+        )
+        .unwrap();
+    }
+    /// Writes a note, chord, or rest (if there are no pitches), split at barlines.
+    fn write_pitches(
+        &mut self,
+        chord: &[Spelling],
+        duration: u32,
+        articulation: Option<Articulation>,
+    ) {
+        let mut left = duration;
+        while left > 0 {
+            let length = left.min(self.measure_left);
+            for value in note_values(length) {
+                let tie_stop = left != duration;
+                left -= value.length();
+                let tie_start = left > 0;
+                let articulation = articulation.filter(|_| !tie_stop);
+                if chord.is_empty() {
+                    self.write_value(None, false, value, (false, false), None);
+                }
+                for (i, &spelling) in chord.iter().enumerate() {
+                    self.write_value(
+                        Some(spelling),
+                        i > 0,
+                        value,
+                        (tie_stop, tie_start),
+                        articulation,
+                    );
+                }
+            }
+            self.measure_left -= length;
+            if self.measure_left == 0 {
+                self.measure_left = self.measure;
+                self.measures.push(String::new());
+            }
+        }
+    }
+    fn write_value(
+        &mut self,
+        spelling: Option<Spelling>,
+        chord: bool,
+        value: NoteValue,
+        (tie_stop, tie_start): (bool, bool),
+        articulation: Option<Articulation>,
+    ) {
+        let out = self.measures.last_mut().unwrap();
+        out.push_str("<note>\n");
+        if chord {
+            out.push_str("<chord/>\n");
+        }
+        match spelling {
+            Some(spelling) => {
+                write!(out, "<pitch>\n<step>{}</step>\n", spelling.step()).unwrap();
+                if spelling.alter != 0 {
+                    writeln!(out, "<alter>{}</alter>", spelling.alter).unwrap();
+                }
+                writeln!(out, "<octave>{}</octave>\n</pitch>", spelling.octave).unwrap();
+            }
+            None => out.push_str("<rest/>\n"),
+        }
+        writeln!(out, "<duration>{}</duration>", value.length()).unwrap();
+        if tie_stop {
+            out.push_str("<tie type=\"stop\"/>\n");
+        }
+        if tie_start {
+            out.push_str("<tie type=\"start\"/>\n");
+        }
+        writeln!(out, "<voice>{}</voice>", self.voice).unwrap();
+        let kind = match value.magnitude {
+            0 => "16th",
+            1 => "eighth",
+            2 => "quarter",
+            3 => "half",
+            4 => "whole",
+            _ => panic!(),
+        };
+        writeln!(out, "<type>{kind}</type>").unwrap();
+        for _ in 0..value.dots {
+            out.push_str("<dot/>\n");
+        }
+        writeln!(out, "<staff>{}</staff>", self.staff).unwrap();
+        if tie_stop || tie_start || articulation.is_some() {
+            out.push_str("<notations>\n");
+            if tie_stop {
+                out.push_str("<tied type=\"stop\"/>\n");
+            }
+            if tie_start {
+                out.push_str("<tied type=\"start\"/>\n");
+            }
+            if let Some(articulation) = articulation {
+                let name = match articulation {
+                    Articulation::Accent => "accent",
+                };
+                writeln!(out, "<articulations>\n<{name}/>\n</articulations>").unwrap();
+            }
+            out.push_str("</notations>\n");
+        }
+        out.push_str("</note>\n");
+    }
+}
+
+impl WriteMusic for MusicXmlWriter {
+    fn write_note(&mut self, _: Pitch, event: &Event) {
+        self.write_dynamics(event);
+        self.write_pitches(&event.spell(self.key), event.duration, event.articulation);
+    }
+    fn write_chord(&mut self, _: &[Pitch], event: &Event) {
+        self.write_dynamics(event);
+        self.write_pitches(&event.spell(self.key), event.duration, event.articulation);
+    }
+    fn write_rest(&mut self, duration: u32) {
+        self.write_pitches(&[], duration, None);
+    }
+}
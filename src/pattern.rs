@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use crate::ConfigError;
+
+/// The most steps a Euclidean rhythm can spread its onsets over.
+const MAX_STEPS: u32 = 256;
+
+/// A repeating pattern of steps on which notes begin.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    /// Whether a note begins on each step of the pattern.
+    pub onsets: Vec<bool>,
+}
+impl Pattern {
+    /// Spreads `onsets` notes as evenly as possible over `steps` steps, starting `rotation`
+    /// steps into the pattern.
+    ///
+    /// For example, 3 onsets over 8 steps is the tresillo, `x..x..x.`.
+    pub fn euclidean(onsets: u32, steps: u32, rotation: u32) -> Self {
+        let (onsets, steps, rotation) = (onsets as u64, steps as u64, rotation as u64);
+        Self {
+            onsets: (0..steps)
+                .map(|i| ((i + rotation % steps) * onsets) % steps < onsets)
+                .collect(),
+        }
+    }
+    /// Whether a note begins on a step, counted from the start of the pattern's first repetition.
+    pub fn is_onset(&self, step: u32) -> bool {
+        self.onsets[step as usize % self.onsets.len()]
+    }
+}
+impl FromStr for Pattern {
+    type Err = ConfigError;
+    /// Parses a Euclidean rhythm such as "euclid:3/8" or "euclid:5/8:2" (with a rotation), an
+    /// explicit pattern of onsets (`x`) and other steps (`.`) such as "pattern:x..x..x.", or
+    /// "tresillo" or "cinquillo".
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let error = || ConfigError::UnknownRhythm(str.to_string());
+        let pattern = match str {
+            "tresillo" => Self::euclidean(3, 8, 0),
+            "cinquillo" => Self::euclidean(5, 8, 2),
+            _ => {
+                if let Some(euclid) = str.strip_prefix("euclid:") {
+                    let (onsets, steps) = euclid.split_once('/').ok_or_else(error)?;
+                    let (steps, rotation) = steps.split_once(':').unwrap_or((steps, "0"));
+                    let number = |n: &str| n.parse::<u32>().map_err(|_| error());
+                    let (onsets, steps) = (number(onsets)?, number(steps)?);
+                    if onsets > steps || steps > MAX_STEPS {
+                        return Err(error());
+                    }
+                    Self::euclidean(onsets, steps, number(rotation)?)
+                } else if let Some(pattern) = str.strip_prefix("pattern:") {
+                    let onsets = pattern.chars().map(|c| match c {
+                        'x' => Ok(true),
+                        '.' => Ok(false),
+                        _ => Err(error()),
+                    });
+                    Self {
+                        onsets: onsets.collect::<Result<_, _>>()?,
+                    }
+                } else {
+                    return Err(error());
+                }
+            }
+        };
+        if !pattern.onsets.contains(&true) {
+            return Err(error());
+        }
+        Ok(pattern)
+    }
+}
@@ -0,0 +1,43 @@
+use rand::Rng;
+
+use crate::SeededRng;
+
+/// A pitch in half-steps, where 0 is Lilypond's `c` (MIDI note 48).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pitch(pub i32);
+impl Pitch {
+    /// The pitch class, between 0 and 11.
+    pub fn note(&self) -> Self {
+        Self(self.0.rem_euclid(12))
+    }
+    pub fn octave(&self) -> i32 {
+        self.0.div_euclid(12)
+    }
+    pub(crate) fn nearest_note(&self, rng: &mut SeededRng, pitches: &[Self]) -> Self {
+        assert!(!pitches.is_empty());
+        let mut best = 12;
+        let mut nearest = *self;
+        for pitch in pitches {
+            let mut diff = pitch.note().0.abs_diff(self.note().0);
+            if diff > 6 {
+                diff = 12 - diff;
+            }
+            if diff == 0 {
+                nearest = pitch.note();
+                break;
+            } else if diff < best {
+                nearest = pitch.note();
+                best = diff;
+            } else if diff == best && rng.gen() {
+                nearest = pitch.note();
+            }
+        }
+        let mut diff = nearest.note().0 - self.note().0;
+        if diff > 6 {
+            diff -= 12;
+        } else if diff < -6 {
+            diff += 12;
+        }
+        Self(self.0 + diff)
+    }
+}
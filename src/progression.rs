@@ -0,0 +1,496 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{ConfigError, Key, Pitch, CYCLE};
+
+/// The original progression, in which each harmony style arranges the four pitches of every
+/// chord in the order they are written.
+const HARMONY: &str = "
+<c e g b> | <c' a f d> | <c e g c'> | <d' b g d> |
+<c e g b> | <c' a f d> | <c e g c'> | <d' b g d> |
+<e g c' e'> | <f' d' c' a> | <g b c' e'> | <g' f' d' b> |
+<c' g e c> | <d f a c'> | <b g e c> | <b, d g f> |
+";
+
+/// The built-in grounds, by name, written in C, and whether they are in minor.
+const GROUNDS: [(&str, &str, bool); 7] = [
+    // The descending tetrachord, as in Dido's Lament, voiced so that the bass falls by step.
+    (
+        "lament",
+        "<c ees g> | <bes, d g> | <aes, c f> | <g, b, d>",
+        true,
+    ),
+    (
+        "folia",
+        "
+        Cm | G | Cm | Bb | Eb | Bb | Cm | G |
+        Cm | G | Cm | Bb | Eb | Bb | Cm G | Cm
+        ",
+        true,
+    ),
+    ("romanesca", "Eb | Bb | Cm | G | Eb | Bb | Cm G | Cm", true),
+    ("pachelbel", "C G | Am Em | F C | F G", false),
+    // After the bass of Bach's Passacaglia in C minor (BWV 582), which opens c g, ees f d ees
+    // b, c, voiced so that the bass keeps its shape.
+    (
+        "bach",
+        "
+        <c ees g> | <g, c ees> | <ees g c'> | <f aes c'> |
+        <d g b> | <ees g c'> | <b, d g> | <c ees g> |
+        <f aes c'> <d f b> | <ees g c'> <c ees g> | <f aes c'> <g b d'> | <c ees g>
+        ",
+        true,
+    ),
+    ("dorian", "Cm7 | F7", true),
+    (
+        "blues",
+        "C7 | F7 | C7 | C7 | F7 | F7 | C7 | C7 | G7 | F7 | C7 | G7",
+        false,
+    ),
+];
+
+/// A chord in a progression.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProgressionChord {
+    /// The pitches the accompaniment plays, relative to the harmony's base.
+    pub pitches: Vec<Pitch>,
+}
+impl ProgressionChord {
+    /// Four pitches for the harmony styles to arrange.
+    ///
+    /// Chords with fewer pitches continue an octave higher; chords with more are cut short.
+    pub fn arpeggio(&self) -> [i32; 4] {
+        let len = self.pitches.len();
+        std::array::from_fn(|i| self.pitches[i % len].0 + 12 * (i / len) as i32)
+    }
+}
+
+/// The chords played by the accompaniment, measure by measure.
+///
+/// The chords within a measure divide its beats evenly, with any extra beats going to the
+/// earlier chords.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Progression {
+    pub measures: Vec<Vec<ProgressionChord>>,
+    /// Whether the chords are already written in a minor key, so that they are only transposed
+    /// into the piece's key, rather than having their thirds and sixths lowered in minor keys.
+    pub minor: bool,
+}
+impl Default for Progression {
+    fn default() -> Self {
+        Self::parse(HARMONY).unwrap()
+    }
+}
+impl Progression {
+    /// A built-in ground, by name. Grounds in minor make the piece's key minor, unless a key is
+    /// set.
+    ///
+    /// Options: "lament", "folia", "romanesca", "pachelbel", "bach", "dorian", "blues"
+    pub fn ground(name: &str) -> Result<Self, ConfigError> {
+        let &(_, ground, minor) = (GROUNDS.iter())
+            .find(|(ground, _, _)| *ground == name)
+            .ok_or_else(|| ConfigError::UnknownGround(name.to_string()))?;
+        Ok(Self {
+            minor,
+            ..Self::parse(ground).unwrap()
+        })
+    }
+    /// Moves a pitch of the piece, generated in C, into a key.
+    pub fn apply(&self, key: Key, pitch: Pitch) -> Pitch {
+        if self.minor {
+            Pitch(pitch.0 + key.offset())
+        } else {
+            key.apply(pitch)
+        }
+    }
+    /// The index of the chord (within its measure) playing on a beat.
+    pub fn chord_index(&self, measure: usize, beat: usize, beats: usize) -> usize {
+        let chords = self.measures[measure].len();
+        (0..chords)
+            .rev()
+            .find(|&i| (i * beats).div_ceil(chords) <= beat)
+            .unwrap()
+    }
+}
+/// Writes the progression in the format it is parsed from, with a line per four measures.
+impl Display for Progression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, measure) in self.measures.iter().enumerate() {
+            for (j, chord) in measure.iter().enumerate() {
+                if j > 0 {
+                    f.write_str(" ")?;
+                }
+                f.write_str("<")?;
+                for (k, &pitch) in chord.pitches.iter().enumerate() {
+                    if k > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}", Key::default().spell(pitch))?;
+                }
+                f.write_str(">")?;
+            }
+            if (i + 1) % CYCLE as usize == 0 {
+                f.write_str(" |\n")?;
+            } else {
+                f.write_str(" | ")?;
+            }
+        }
+        Ok(())
+    }
+}
+/// Writes the progression on a single line.
+impl std::fmt::Debug for Progression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = self.to_string();
+        write!(f, "Progression({:?})", text.trim_end().replace('\n', " "))
+    }
+}
+
+/// A problem in a progression's text, with the position (counting from 1) where it was found.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+impl std::error::Error for ParseError {}
+
+/// Reads characters while tracking their position.
+struct Cursor<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+    /// Parses a pitch in Lilypond's absolute notation, where `c` is 0.
+    fn pitch(&mut self) -> Result<Pitch, ParseError> {
+        const NATURALS: [(char, i32); 7] = [
+            ('c', 0),
+            ('d', 2),
+            ('e', 4),
+            ('f', 5),
+            ('g', 7),
+            ('a', 9),
+            ('b', 11),
+        ];
+        let letter = self.peek();
+        let Some(&(letter, mut pitch)) = NATURALS.iter().find(|(l, _)| Some(*l) == letter) else {
+            return Err(self.error(match letter {
+                Some(ch) => format!("Expected a note name, found {ch:?}"),
+                None => "Expected a note name".to_string(),
+            }));
+        };
+        self.next();
+        if matches!(letter, 'a' | 'e') && self.peek() == Some('s') {
+            self.next();
+            pitch -= 1;
+        }
+        loop {
+            match self.peek() {
+                Some('i') => {
+                    self.next();
+                    self.expect('s')?;
+                    pitch += 1;
+                }
+                Some('e') => {
+                    self.next();
+                    self.expect('s')?;
+                    pitch -= 1;
+                }
+                Some('\'') => {
+                    self.next();
+                    pitch += 12;
+                }
+                Some(',') => {
+                    self.next();
+                    pitch -= 12;
+                }
+                _ => return Ok(Pitch(pitch)),
+            }
+        }
+    }
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(ch) if ch == expected => {
+                self.next();
+                Ok(())
+            }
+            Some(ch) => Err(self.error(format!("Expected {expected:?}, found {ch:?}"))),
+            None => Err(self.error(format!("Expected {expected:?}"))),
+        }
+    }
+    /// Parses a chord, written as pitches between angle brackets.
+    fn chord(&mut self) -> Result<ProgressionChord, ParseError> {
+        let (line, column) = (self.line, self.column);
+        self.expect('<')?;
+        let mut pitches = vec![];
+        loop {
+            match self.peek() {
+                Some(' ' | '\t') => {
+                    self.next();
+                }
+                Some('>') => {
+                    self.next();
+                    break;
+                }
+                Some(_) => pitches.push(self.pitch()?),
+                None => return Err(self.error("Expected '>' to end the chord")),
+            }
+        }
+        if pitches.is_empty() {
+            return Err(ParseError {
+                line,
+                column,
+                message: "Chords must have at least one pitch".to_string(),
+            });
+        }
+        Ok(ProgressionChord { pitches })
+    }
+    /// Parses a chord symbol, which continues until whitespace, `|`, `%`, or `<`.
+    fn symbol(&mut self) -> Result<ProgressionChord, ParseError> {
+        let (line, column) = (self.line, self.column);
+        let mut symbol = vec![];
+        while let Some(ch) = self
+            .peek()
+            .filter(|ch| !ch.is_whitespace() && !"|%<".contains(*ch))
+        {
+            symbol.push(ch);
+            self.next();
+        }
+        let pitches = parse_symbol(&symbol).map_err(|(offset, message)| ParseError {
+            line,
+            column: column + offset,
+            message,
+        })?;
+        Ok(ProgressionChord { pitches })
+    }
+}
+
+/// The intervals above the root of each chord quality.
+const QUALITIES: [(&str, &[i32]); 12] = [
+    ("maj7", &[4, 7, 11]),
+    ("m7", &[3, 7, 10]),
+    ("dim7", &[3, 6, 9]),
+    ("dim", &[3, 6]),
+    ("aug", &[4, 8]),
+    ("sus2", &[2, 7]),
+    ("sus4", &[5, 7]),
+    ("7sus2", &[2, 7, 10]),
+    ("7sus4", &[5, 7, 10]),
+    ("m", &[3, 7]),
+    ("7", &[4, 7, 10]),
+    ("", &[4, 7]),
+];
+
+/// Parses a chord symbol: a root, a quality, an optional added ninth, and an optional bass note
+/// after a slash.
+///
+/// The root is placed in the octave above the harmony's base, with the other tones stacked
+/// above it and the bass below it. Errors are returned with the offset of the character that
+/// could not be parsed.
+fn parse_symbol(symbol: &[char]) -> Result<Vec<Pitch>, (usize, String)> {
+    let mut offset = 0;
+    let root = parse_root(symbol, &mut offset)?;
+    let rest: String = symbol[offset..].iter().collect();
+    let name = &rest[..rest.find('/').unwrap_or(rest.len())];
+    let name = &name[..name.find("add9").unwrap_or(name.len())];
+    let Some((_, intervals)) = QUALITIES.iter().find(|(quality, _)| *quality == name) else {
+        return Err((offset, format!("Unknown chord quality {name:?}")));
+    };
+    offset += name.chars().count();
+    let mut pitches = vec![root];
+    pitches.extend(intervals.iter().map(|interval| Pitch(root.0 + interval)));
+    let rest: String = symbol[offset..].iter().collect();
+    if rest.starts_with("add9") {
+        pitches.push(Pitch(root.0 + 14));
+        offset += 4;
+    }
+    let slash = symbol.get(offset) == Some(&'/');
+    if slash {
+        offset += 1;
+        let bass = parse_root(symbol, &mut offset)?;
+        let bass = Pitch(root.0 - (root.0 - bass.0).rem_euclid(12));
+        pitches.retain(|pitch| pitch.note() != bass.note() || pitch.0 == root.0);
+        if bass != root {
+            pitches.insert(0, bass);
+        }
+    }
+    if offset < symbol.len() {
+        let rest: String = symbol[offset..].iter().collect();
+        let after = if slash { "the bass note" } else { "the chord" };
+        return Err((offset, format!("Unexpected {rest:?} after {after}")));
+    }
+    Ok(pitches)
+}
+
+/// Parses a note name from A to G, followed by any number of sharps (`#`) or flats (`b`).
+fn parse_root(symbol: &[char], offset: &mut usize) -> Result<Pitch, (usize, String)> {
+    const NATURALS: [(char, i32); 7] = [
+        ('C', 0),
+        ('D', 2),
+        ('E', 4),
+        ('F', 5),
+        ('G', 7),
+        ('A', 9),
+        ('B', 11),
+    ];
+    let letter = symbol.get(*offset);
+    let Some(&(_, mut pitch)) = NATURALS.iter().find(|(l, _)| Some(l) == letter) else {
+        return Err((
+            *offset,
+            match letter {
+                Some(ch) => format!("Expected a note name from A to G, found {ch:?}"),
+                None => "Expected a note name from A to G".to_string(),
+            },
+        ));
+    };
+    *offset += 1;
+    while let Some(ch) = symbol.get(*offset) {
+        match ch {
+            '#' | '♯' => pitch += 1,
+            'b' | '♭' => pitch -= 1,
+            _ => break,
+        }
+        *offset += 1;
+    }
+    Ok(Pitch(pitch.rem_euclid(12)))
+}
+
+impl FromStr for Progression {
+    type Err = ConfigError;
+    /// Parses measures separated by `|` or line breaks, each containing one or more chords.
+    ///
+    /// Chords are written either as in Lilypond (such as `<c e g b>`, with `c` as the harmony's
+    /// base) or as chord symbols (such as `Cmaj7` or `G7/B`). `%` begins a comment, which lasts
+    /// until the end of the line.
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        Self::parse(str).map_err(ConfigError::Progression)
+    }
+}
+impl Progression {
+    fn parse(str: &str) -> Result<Self, ParseError> {
+        let mut cursor = Cursor {
+            chars: str.chars().peekable(),
+            line: 1,
+            column: 1,
+        };
+        let mut measures = vec![];
+        let mut measure = vec![];
+        while let Some(ch) = cursor.peek() {
+            match ch {
+                '%' => {
+                    while cursor.peek().is_some_and(|ch| ch != '\n') {
+                        cursor.next();
+                    }
+                }
+                '\n' => {
+                    cursor.next();
+                    if !measure.is_empty() {
+                        measures.push(std::mem::take(&mut measure));
+                    }
+                }
+                '|' => {
+                    if measure.is_empty() {
+                        return Err(cursor.error("Expected a chord before '|'"));
+                    }
+                    cursor.next();
+                    measures.push(std::mem::take(&mut measure));
+                }
+                '<' => measure.push(cursor.chord()?),
+                'A'..='G' => measure.push(cursor.symbol()?),
+                ch if ch.is_whitespace() => {
+                    cursor.next();
+                }
+                ch => return Err(cursor.error(format!("Expected a chord, found {ch:?}"))),
+            }
+        }
+        if !measure.is_empty() {
+            measures.push(measure);
+        }
+        if measures.is_empty() {
+            return Err(cursor.error("The progression must have at least one measure"));
+        }
+        Ok(Self {
+            measures,
+            minor: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> ParseError {
+        Progression::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn slash_chord() {
+        assert_eq!(
+            Progression::parse("Gm/Bb"),
+            Progression::parse("<bes, g d'>")
+        );
+    }
+
+    #[test]
+    fn added_ninth() {
+        assert_eq!(
+            Progression::parse("Cadd9"),
+            Progression::parse("<c e g d'>")
+        );
+        assert_eq!(
+            Progression::parse("Amadd9"),
+            Progression::parse("<a c' e' b'>")
+        );
+    }
+
+    #[test]
+    fn unknown_quality() {
+        let error = error("C | Cx7");
+        assert_eq!((error.line, error.column), (1, 6));
+        assert_eq!(error.message, "Unknown chord quality \"x7\"");
+    }
+
+    #[test]
+    fn bad_bass() {
+        let error = error("C\nG/H");
+        assert_eq!((error.line, error.column), (2, 3));
+        assert_eq!(error.message, "Expected a note name from A to G, found 'H'");
+    }
+
+    #[test]
+    fn repeated_ninth() {
+        let error = error("Cadd9add9");
+        assert_eq!((error.line, error.column), (1, 6));
+        assert_eq!(error.message, "Unexpected \"add9\" after the chord");
+    }
+}
@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+use crate::{score::sounding, ConfigError, Pitch, Score, SeededRng};
+
+/// A set of pitch classes which the melody keeps to, in half-steps above the key's tonic.
+#[derive(Clone, Debug)]
+pub struct Scale {
+    /// The pitch classes of the scale, between 0 and 11.
+    pub pitches: Vec<i32>,
+}
+impl FromStr for Scale {
+    type Err = ConfigError;
+    /// Parses a scale's name, such as "dorian" or "blues", or a list of pitch classes above the
+    /// tonic, such as "0,2,4,7,9".
+    fn from_str(str: &str) -> Result<Self, ConfigError> {
+        let pitches = match str {
+            "major" | "ionian" => vec![0, 2, 4, 5, 7, 9, 11],
+            "minor" | "natural-minor" | "aeolian" => vec![0, 2, 3, 5, 7, 8, 10],
+            "harmonic-minor" => vec![0, 2, 3, 5, 7, 8, 11],
+            "melodic-minor" => vec![0, 2, 3, 5, 7, 9, 11],
+            "dorian" => vec![0, 2, 3, 5, 7, 9, 10],
+            "phrygian" => vec![0, 1, 3, 5, 7, 8, 10],
+            "lydian" => vec![0, 2, 4, 6, 7, 9, 11],
+            "mixolydian" => vec![0, 2, 4, 5, 7, 9, 10],
+            "locrian" => vec![0, 1, 3, 5, 6, 8, 10],
+            "pentatonic" | "major-pentatonic" => vec![0, 2, 4, 7, 9],
+            "minor-pentatonic" => vec![0, 3, 5, 7, 10],
+            "blues" => vec![0, 3, 5, 6, 7, 10],
+            "whole-tone" => vec![0, 2, 4, 6, 8, 10],
+            _ => {
+                let error = || ConfigError::UnknownScale(str.to_string());
+                let separator = |c: char| c == ',' || c.is_whitespace();
+                let pitches = (str.split(separator).filter(|pitch| !pitch.is_empty()))
+                    .map(|pitch| match pitch.parse() {
+                        Ok(pitch @ 0..=11) => Ok(pitch),
+                        _ => Err(error()),
+                    })
+                    .collect::<Result<Vec<i32>, _>>()?;
+                if pitches.is_empty() {
+                    return Err(error());
+                }
+                pitches
+            }
+        };
+        Ok(Self { pitches })
+    }
+}
+
+/// Moves every note of a melodic part which is not a tone of the chord sounding to the nearest
+/// pitch of the configured scale, in the score's key.
+pub(crate) fn quantize_melody(score: &mut Score, part: usize, rng: &mut SeededRng) {
+    let Score {
+        config,
+        key,
+        chords,
+        parts,
+        ..
+    } = score;
+    let Some(scale) = &config.scale else {
+        return;
+    };
+    let scale: Vec<Pitch> = (scale.pitches.iter())
+        .map(|&pitch| Pitch(key.tonic() + pitch))
+        .collect();
+    if let Some(melody) = parts.get_mut(part).and_then(|part| part.voices.first_mut()) {
+        for event in &mut melody.events {
+            let chord = sounding(chords, event.start);
+            for pitch in &mut event.pitches {
+                let chord_tone = chord.is_some_and(|chord| {
+                    (chord.pitches.iter()).any(|tone| tone.note() == pitch.note())
+                });
+                if !chord_tone {
+                    *pitch = pitch.nearest_note(rng, &scale);
+                }
+            }
+        }
+    }
+}
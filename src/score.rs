@@ -0,0 +1,368 @@
+use crate::{Config, Instrument, Key, Pitch, Spelling, TimeSignature};
+
+/// A pitch held for a number of steps, as produced by the generator.
+#[derive(Clone, Copy, Debug)]
+pub struct Note {
+    pub pitch: Pitch,
+    pub duration: u32,
+}
+
+/// A complete generated piece, independent of any output format.
+///
+/// The generator produces a score once; each backend then renders it separately.
+#[derive(Clone, Debug)]
+pub struct Score {
+    /// The configuration the piece was generated from.
+    pub config: Config,
+    /// The key the piece is written in.
+    pub key: Key,
+    pub time: TimeSignature,
+    /// The length of the piece, in steps.
+    pub length: u32,
+    /// The rehearsal marks naming each section, in order.
+    pub rehearsals: Vec<Rehearsal>,
+    /// Changes from the configured tempo, in order.
+    pub tempo_changes: Vec<TempoChange>,
+    /// The chords sounded by the accompaniment, in order.
+    pub chords: Vec<Chord>,
+    /// The staves of the piece, from top to bottom.
+    pub parts: Vec<Part>,
+}
+
+impl Score {
+    /// Replaces every pitch in the score, then spells the new pitches.
+    pub fn map_pitches(&mut self, mut f: impl FnMut(Pitch) -> Pitch) {
+        for chord in &mut self.chords {
+            for pitch in &mut chord.pitches {
+                *pitch = f(*pitch);
+            }
+        }
+        for part in &mut self.parts {
+            for voice in &mut part.voices {
+                for event in &mut voice.events {
+                    for pitch in &mut event.pitches {
+                        *pitch = f(*pitch);
+                    }
+                }
+            }
+        }
+        self.respell();
+    }
+    /// The chord sounding at a step, if any.
+    pub fn chord_at(&self, time: u32) -> Option<&Chord> {
+        sounding(&self.chords, time)
+    }
+    /// Chooses how every pitch in the score is written, based on the key, the chord sounding,
+    /// and the direction of the melody.
+    pub fn respell(&mut self) {
+        let key = self.key;
+        let mut parts = std::mem::take(&mut self.parts);
+        for part in &mut parts {
+            for voice in &mut part.voices {
+                for i in 0..voice.events.len() {
+                    let next = voice.events[i + 1..]
+                        .iter()
+                        .find_map(|event| event.pitches.first().copied());
+                    let event = &voice.events[i];
+                    let chord = self.chord_at(event.start).map(|chord| &chord.pitches[..]);
+                    let spelling = event
+                        .pitches
+                        .iter()
+                        .map(|&pitch| key.spell_in(pitch, chord, next))
+                        .collect();
+                    voice.events[i].spelling = spelling;
+                }
+            }
+        }
+        self.parts = parts;
+    }
+}
+
+/// The chord sounding at a step, among chords sorted by their start.
+pub(crate) fn sounding(chords: &[Chord], time: u32) -> Option<&Chord> {
+    let index = chords.partition_point(|chord| chord.start <= time);
+    chords[..index]
+        .last()
+        .filter(|chord| time < chord.start + chord.duration)
+}
+
+/// A rehearsal mark, written above the first staff at the start of a measure.
+#[derive(Clone, Debug)]
+pub struct Rehearsal {
+    /// The step at which the marked section begins, at a barline.
+    pub start: u32,
+    pub name: String,
+}
+
+/// A new tempo, beginning partway through the score.
+#[derive(Clone, Copy, Debug)]
+pub struct TempoChange {
+    /// The step at which the tempo changes.
+    pub start: u32,
+    /// The number of beats per minute.
+    pub tempo: f32,
+}
+
+/// A set of pitches sounding together in the accompaniment.
+#[derive(Clone, Debug)]
+pub struct Chord {
+    /// The step at which the chord begins.
+    pub start: u32,
+    /// The length of the chord, in steps.
+    pub duration: u32,
+    pub pitches: Vec<Pitch>,
+}
+
+/// A single staff of a score.
+#[derive(Clone, Debug)]
+pub struct Part {
+    pub name: String,
+    pub clef: Clef,
+    pub instrument: Instrument,
+    /// Simultaneous voices sharing the part's staff.
+    pub voices: Vec<Voice>,
+}
+impl Part {
+    /// The key and voices of the part as its instrument reads them, in a score in `key`.
+    ///
+    /// Transposing instruments are written in a different key than they sound in.
+    pub fn written(&self, key: Key) -> (Key, Vec<Voice>) {
+        let (mut letters, half_steps) = self.instrument.transposition();
+        if half_steps == 0 {
+            return (key, self.voices.clone());
+        }
+        // Keys with more than seven sharps or flats are written as their enharmonic equivalents,
+        // a letter higher or lower.
+        let fifths = key.transpose(letters, half_steps).fifths();
+        if fifths > 7 {
+            letters += 1;
+        } else if fifths < -7 {
+            letters += 6;
+        }
+        let mut voices = self.voices.clone();
+        for event in voices.iter_mut().flat_map(|voice| &mut voice.events) {
+            for pitch in &mut event.pitches {
+                pitch.0 += half_steps;
+            }
+            for (spelling, pitch) in event.spelling.iter_mut().zip(&event.pitches) {
+                *spelling = Spelling::new(*pitch, (spelling.letter + letters) % 7);
+            }
+        }
+        (key.transpose(letters, half_steps), voices)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Clef {
+    Treble,
+    Bass,
+    /// A treble clef sounding an octave lower, as for tenors and guitars.
+    Treble8,
+}
+impl Clef {
+    /// The clef as Lilypond writes it.
+    pub fn to_name(self) -> &'static str {
+        match self {
+            Clef::Treble => "treble",
+            Clef::Bass => "bass",
+            Clef::Treble8 => "\"treble_8\"",
+        }
+    }
+}
+
+/// A sequence of events, each beginning when the previous one ends.
+#[derive(Clone, Debug, Default)]
+pub struct Voice {
+    pub events: Vec<Event>,
+}
+impl Voice {
+    /// The step at which the voice's last event ends.
+    pub fn end(&self) -> u32 {
+        self.events
+            .last()
+            .map_or(0, |event| event.start + event.duration)
+    }
+    pub fn push(&mut self, pitches: Vec<Pitch>, duration: u32, velocity: u8) {
+        self.events.push(Event {
+            start: self.end(),
+            duration,
+            pitches,
+            spelling: vec![],
+            velocity,
+            articulation: None,
+            dynamic: None,
+            hairpin: None,
+        });
+    }
+    pub fn note(&mut self, Note { pitch, duration }: Note, velocity: u8) {
+        self.push(vec![pitch], duration, velocity);
+    }
+    pub fn chord(&mut self, chord: &[Pitch], duration: u32, velocity: u8) {
+        self.push(chord.to_vec(), duration, velocity);
+    }
+    pub fn rest(&mut self, duration: u32) {
+        self.push(vec![], duration, 0);
+    }
+    /// Moves any pitches outside of a range by octaves into it, which must span an octave.
+    pub fn fold(&mut self, low: i32, high: i32) {
+        for pitch in self.events.iter_mut().flat_map(|event| &mut event.pitches) {
+            while pitch.0 > high {
+                pitch.0 -= 12;
+            }
+            while pitch.0 < low {
+                pitch.0 += 12;
+            }
+        }
+    }
+    pub fn render(&self, out: &mut impl WriteMusic) {
+        for event in &self.events {
+            out.write_event(event);
+        }
+    }
+}
+
+/// A note, chord, or rest (if there are no pitches).
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// The step at which the event begins.
+    pub start: u32,
+    /// The length of the event, in steps.
+    pub duration: u32,
+    pub pitches: Vec<Pitch>,
+    /// How each pitch is written, as chosen by [`Score::respell`].
+    pub spelling: Vec<Spelling>,
+    /// The MIDI velocity of the event, between 1 and 127 (0 for rests).
+    pub velocity: u8,
+    pub articulation: Option<Articulation>,
+    /// A dynamics mark written at the start of the event.
+    pub dynamic: Option<Dynamic>,
+    /// A hairpin beginning with the event, which lasts until the next dynamics mark.
+    pub hairpin: Option<Hairpin>,
+}
+
+impl Event {
+    /// How each pitch is written, spelling them in the key if the score has not.
+    pub fn spell(&self, key: Key) -> Vec<Spelling> {
+        if self.spelling.len() == self.pitches.len() {
+            self.spelling.clone()
+        } else {
+            self.pitches.iter().map(|&pitch| key.spell(pitch)).collect()
+        }
+    }
+}
+
+/// A mark on how a note is played.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Articulation {
+    Accent,
+}
+impl Articulation {
+    pub(crate) fn to_lilypond(self) -> &'static str {
+        match self {
+            Articulation::Accent => "->",
+        }
+    }
+}
+
+/// A written dynamic level.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Dynamic {
+    Pianissimo,
+    Piano,
+    MezzoPiano,
+    MezzoForte,
+    Forte,
+    Fortissimo,
+}
+impl Dynamic {
+    /// Every level, from softest to loudest.
+    pub const ALL: [Dynamic; 6] = [
+        Dynamic::Pianissimo,
+        Dynamic::Piano,
+        Dynamic::MezzoPiano,
+        Dynamic::MezzoForte,
+        Dynamic::Forte,
+        Dynamic::Fortissimo,
+    ];
+    /// The abbreviation written in the score, such as "mf".
+    pub fn to_name(self) -> &'static str {
+        match self {
+            Dynamic::Pianissimo => "pp",
+            Dynamic::Piano => "p",
+            Dynamic::MezzoPiano => "mp",
+            Dynamic::MezzoForte => "mf",
+            Dynamic::Forte => "f",
+            Dynamic::Fortissimo => "ff",
+        }
+    }
+    /// The MIDI velocity of the level, where `volume` is mezzo-forte.
+    pub fn velocity(self, volume: u8) -> f32 {
+        let scale = match self {
+            Dynamic::Pianissimo => 0.5,
+            Dynamic::Piano => 0.65,
+            Dynamic::MezzoPiano => 0.8,
+            Dynamic::MezzoForte => 1.0,
+            Dynamic::Forte => 1.2,
+            Dynamic::Fortissimo => 1.4,
+        };
+        (volume as f32 * scale).clamp(1.0, 127.0)
+    }
+}
+
+/// A gradual change in dynamics.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Hairpin {
+    Crescendo,
+    Decrescendo,
+}
+
+/// A backend which can render the events of a voice.
+pub trait WriteMusic {
+    fn write_note(&mut self, pitch: Pitch, event: &Event);
+    fn write_chord(&mut self, chord: &[Pitch], event: &Event);
+    fn write_rest(&mut self, duration: u32);
+    fn write_event(&mut self, event: &Event) {
+        match &event.pitches[..] {
+            [] => self.write_rest(event.duration),
+            &[pitch] => self.write_note(pitch, event),
+            chord => self.write_chord(chord, event),
+        }
+    }
+}
+
+/// A notated duration: a power-of-two number of steps, with some number of dots.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct NoteValue {
+    /// The base-2 logarithm of the undotted length, in steps (0 is a sixteenth note).
+    pub magnitude: u32,
+    pub dots: u32,
+}
+impl NoteValue {
+    /// The length of the value, in steps.
+    pub fn length(self) -> u32 {
+        (0..=self.dots).map(|dot| 1 << (self.magnitude - dot)).sum()
+    }
+}
+
+/// Splits a duration into dotted note values, longest first.
+pub(crate) fn note_values(duration: u32) -> Vec<NoteValue> {
+    let mut values = vec![];
+    let mut magnitude = duration.ilog2() as i32;
+    while magnitude >= 0 {
+        if duration & (1 << magnitude) != 0 {
+            let mut value = NoteValue {
+                magnitude: magnitude as u32,
+                dots: 0,
+            };
+            magnitude -= 1;
+            while magnitude >= 0 && duration & (1 << magnitude) != 0 {
+                value.dots += 1;
+                magnitude -= 1;
+            }
+            values.push(value);
+        } else {
+            magnitude -= 1;
+        }
+    }
+    values
+}